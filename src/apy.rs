@@ -1,19 +1,22 @@
-use std::{ops::Mul, str::FromStr};
-use log::info;
+use log::{info, warn};
 use serde::{Serialize, Deserialize};
-use solana_client::rpc_client::RpcClient;
-use solana_sdk::{program_pack::Pack, pubkey::Pubkey};
-use spl_token_lending::state::Reserve;
-use spl_token_lending::math::{Decimal, TryDiv};
+use spl_token_lending::state::{Reserve, SLOTS_PER_YEAR};
+use spl_token_lending::math::{Decimal, Rate, TryAdd, TryDiv, TryMul, TrySub, WAD};
 
-use crate::utils::{TokenRewardStat, Reward};
-use crate::{AssetSymbol, PRODUCTION_CONFIG_JSON, utils::ProgramConfig, Stats};
+use crate::error::{AssetError, Error, Result};
+use crate::utils::{default_market, AssetSymbol, Market, ProgramConfig, TokenRewardStat, Reward};
+
+/// Days a loan is assumed to be held for, the borrow fee is paid once per loan and spread over them
+pub const BORROW_FEE_HOLDING_DAYS: u64 = 30;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct APY {
+    #[serde(default = "default_market")]
+    pub market: String,
     pub asset: AssetSymbol,
     pub name: String,
     pub price: f64,
+    /// Interest compounded every slot plus the rewards, as shown on solend.fi
     pub supply: f64,
     pub borrow: f64,
     pub supply_rewards: f64,
@@ -21,115 +24,150 @@ pub struct APY {
     pub weight_supply: String,
     pub weight_borrow: String,
     pub mnde_supply_rewards: Option<f64>,
+    /// Share of the supplied liquidity that is borrowed, missing in data points stored before it was
+    #[serde(default)]
+    pub utilization: Option<f64>,
+    /// Interest without compounding plus the rewards, missing in data points stored before it was,
+    /// whose `supply` and `borrow` hold this rate
+    #[serde(default)]
+    pub supply_apr: Option<f64>,
+    #[serde(default)]
+    pub borrow_apr: Option<f64>,
+    /// Supply APY if depositors also got the borrow fee of the reserve and the share of the interest
+    /// the protocol keeps, i.e. everything borrowers pay per unit of supply
+    #[serde(default)]
+    pub supply_gross: Option<f64>,
+}
+
+/// APYs of the assets that could be calculated, along with the reason for those that could not
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct APYReport {
+    pub apys: Vec<APY>,
+    pub errors: Vec<AssetError>,
 }
 
 impl APY {
-    pub fn from_assets(rpc_client: &RpcClient, assets: &Vec<AssetSymbol>) -> Vec<Self> {
-        let program_config : ProgramConfig = serde_json::from_str(PRODUCTION_CONFIG_JSON).unwrap();
-        // Get all production PubKeys
-        let mut account_pks = Vec::<Pubkey>::new();
-        for &asset_symbol in assets {
-            let reserve_json= program_config.markets[0].reserves.iter().find(|e| e.asset == asset_symbol).unwrap();
-            let reserve_pk = Pubkey::from_str(&reserve_json.address.to_string()).unwrap();
-            account_pks.push(reserve_pk);
+    /// Calculates the APYs of `assets` from their already fetched `reserves`, in the same order
+    pub fn from_reserves(reserves: &[Result<Reserve>], program_config: &ProgramConfig, market: &Market, assets: &[AssetSymbol], reward_stats: &serde_json::Value, external_reward_stats: &serde_json::Value, slnd_price: f64, mnde_price: f64) -> APYReport {
+        let mut report = APYReport { apys: Vec::new(), errors: Vec::new() };
+        for (index, reserve) in reserves.iter().enumerate() {
+            let asset_symbol = assets[index].clone();
+            let apy = match reserve {
+                Ok(reserve) => Self::from_reserve(reserve, program_config, market, asset_symbol.clone(), reward_stats, external_reward_stats, slnd_price, mnde_price),
+                Err(e) => {
+                    warn!("Could not read {} reserve: {}", asset_symbol, e);
+                    report.errors.push(AssetError::new(asset_symbol, e));
+                    continue;
+                },
+            };
+            match apy {
+                Ok(apy) => report.apys.push(apy),
+                Err(e) => {
+                    warn!("Could not calculate {} APY: {}", asset_symbol, e);
+                    report.errors.push(AssetError::new(asset_symbol, &e));
+                },
+            }
         }
+        return report;
+    }
 
-        let reward_stats : serde_json::Value = reqwest::blocking::get("https://api.solend.fi/liquidity-mining/reward-stats").unwrap().json().unwrap();
-        let external_reward_stats : serde_json::Value = reqwest::blocking::get("https://api.solend.fi/liquidity-mining/external-reward-stats").unwrap().json().unwrap();
-        let slnd_price = Stats::get_slnd_price(rpc_client);
-        let mnde_price = Stats::get_mnde_price(rpc_client);
-
-        let accounts = rpc_client.get_multiple_accounts(&account_pks).unwrap();
-        let mut result = Vec::<APY>::new();
-        for (index, account) in accounts.iter().enumerate() {
-            let data = account.as_ref().unwrap().data.clone();
-            let reserve = Reserve::unpack_from_slice(&data).unwrap();
-            let asset_symbol = assets[index];
-            result.push(Self::from_reserve(&reserve, asset_symbol, &reward_stats, &external_reward_stats, slnd_price, mnde_price));
-        }
-        return result;
-    }
-
-    pub fn from_asset(rpc_client: &RpcClient, asset_symbol: AssetSymbol) -> Self {
-        let program_config : ProgramConfig = serde_json::from_str(PRODUCTION_CONFIG_JSON).unwrap();
-        let reserve_json= program_config.markets[0].reserves.iter().find(|e| e.asset == asset_symbol).unwrap();
-        let reserve_pk = Pubkey::from_str(&reserve_json.address.to_string()).unwrap();
-        let account_data = rpc_client.get_account_data(&reserve_pk).unwrap();
-        let reserve = Reserve::unpack_from_slice(&account_data).unwrap();
-        let reward_stats : serde_json::Value = reqwest::blocking::get("https://api.solend.fi/liquidity-mining/reward-stats").unwrap().json().unwrap();
-        let external_reward_stats : serde_json::Value = reqwest::blocking::get("https://api.solend.fi/liquidity-mining/external-reward-stats").unwrap().json().unwrap();
-        let slnd_price = Stats::get_slnd_price(rpc_client);
-        let mnde_price = Stats::get_slnd_price(rpc_client);
-        
-        return Self::from_reserve(&reserve, asset_symbol, &reward_stats, &external_reward_stats, slnd_price, mnde_price);
-    }
-
-    fn from_reserve(reserve: &Reserve, asset_symbol: AssetSymbol, reward_stats: &serde_json::Value, external_reward_stats: &serde_json::Value, slnd_price: f64, mnde_price: f64) -> Self {
-        info!("Calculate {} APY", asset_symbol);
-        let market_price = (reserve.liquidity.market_price.to_scaled_val().unwrap() as f64) / 1_000_000_000_000_000_000f64;
-        let supply_apy = Self::calculate_supply(&reserve);
-        let borrow_apy = Self::calculate_borrow(&reserve);
-        let rewards = Self::calculate_annual_tokens(&reserve, asset_symbol, &reward_stats, &external_reward_stats, slnd_price, mnde_price);
-        let mnde_supply_rewards = if let Some(value) = rewards.4 { value } else { 0f64 }; 
-
-        return Self {
+    pub fn from_reserve(reserve: &Reserve, program_config: &ProgramConfig, market: &Market, asset_symbol: AssetSymbol, reward_stats: &serde_json::Value, external_reward_stats: &serde_json::Value, slnd_price: f64, mnde_price: f64) -> Result<Self> {
+        info!("Calculate {} APY in {} market", asset_symbol, market.name);
+        let (supply_gross_apr, supply_apr) = Self::calculate_supply(reserve)?;
+        let borrow_apr = Self::calculate_borrow(reserve)?;
+        let rewards = Self::calculate_annual_tokens(reserve, reward_stats, external_reward_stats, slnd_price, mnde_price)?;
+        let supply_rewards = decimal_to_f64(rewards.0)?;
+        let borrow_rewards = decimal_to_f64(rewards.1)?;
+        let mnde_supply_rewards = rewards.4.map(decimal_to_f64).transpose()?;
+        let all_supply_rewards = supply_rewards + mnde_supply_rewards.unwrap_or(0f64);
+
+        // Assets are keyed by mint, reserves whose mint is not listed yet fall back to the symbol
+        let mint_address = reserve.liquidity.mint_pubkey.to_string();
+        let name = match program_config.asset_by_mint(&mint_address) {
+            Some(asset) => asset.name.clone(),
+            None => asset_symbol.to_string(),
+        };
+
+        // The rates are only converted to floats here, the rewards are added to them as floats since
+        // the borrow APY less its rewards can be negative
+        return Ok(Self {
+            market: market.name.clone(),
             asset: asset_symbol,
-            name: asset_symbol.name(),
-            price: market_price,
-            supply: supply_apy + rewards.0 + mnde_supply_rewards,
-            borrow: borrow_apy - rewards.1,
-            supply_rewards: rewards.0,
-            borrow_rewards: rewards.1,
+            name,
+            price: decimal_to_f64(reserve.liquidity.market_price)?,
+            // Rewards are paid out in tokens, they do not compound
+            supply: rate_to_f64(Self::compound(supply_apr)?) + all_supply_rewards,
+            borrow: rate_to_f64(Self::compound(borrow_apr)?) - borrow_rewards,
+            supply_rewards,
+            borrow_rewards,
             weight_supply: rewards.2,
             weight_borrow: rewards.3,
-            mnde_supply_rewards: rewards.4,
-        };
+            mnde_supply_rewards,
+            utilization: Some(rate_to_f64(Self::calculate_utilization_ratio(reserve)?)),
+            supply_apr: Some(rate_to_f64(supply_apr) + all_supply_rewards),
+            borrow_apr: Some(rate_to_f64(borrow_apr) - borrow_rewards),
+            supply_gross: Some(rate_to_f64(Self::compound(supply_gross_apr)?) + all_supply_rewards),
+        });
     }
 
-    fn calculate_borrow(reserve: &Reserve) -> f64 {
-        let current_utilization = Self::calculate_utilization_ratio(reserve);
-        let optimal_utilization = reserve.config.optimal_utilization_rate as f64 / 100f64;
-        let borrow_apy = {
-            if optimal_utilization == 1f64 || current_utilization < optimal_utilization {
-                let normalized_factor = current_utilization / optimal_utilization;
-                let optimal_borrow_rate = reserve.config.optimal_borrow_rate as f64 / 100f64;
-                let min_borrow_rate = reserve.config.min_borrow_rate as f64 / 100f64;
-                normalized_factor * (optimal_borrow_rate - min_borrow_rate) + min_borrow_rate
-            } else {
-                let normalized_factor = (current_utilization - optimal_utilization) / (1f64 - optimal_utilization);
-                let optimal_borrow_rate = reserve.config.optimal_borrow_rate as f64 / 100f64;
-                let max_borrow_rate = reserve.config.max_borrow_rate as f64 / 100f64;
-                normalized_factor * (max_borrow_rate - optimal_borrow_rate) + optimal_borrow_rate
-            }
-        };
-        return borrow_apy;
+    /// APY of an interest rate compounded every slot, the way the program accrues it
+    pub fn compound(apr: Rate) -> Result<Rate> {
+        let slot_rate = apr.try_div(SLOTS_PER_YEAR)?;
+        return Ok(Rate::one().try_add(slot_rate)?.try_pow(SLOTS_PER_YEAR)?.try_sub(Rate::one())?);
+    }
+
+    /// Converts an APY stored before the interest was compounded, whose `supply` and `borrow` hold
+    /// the APRs with the rewards. Returns false when it already has its APRs.
+    pub fn upgrade_from_apr(&mut self) -> bool {
+        if self.supply_apr.is_some() || self.borrow_apr.is_some() {
+            return false;
+        }
+        let supply_rewards = self.supply_rewards + self.mnde_supply_rewards.unwrap_or(0f64);
+        self.supply_apr = Some(self.supply);
+        self.borrow_apr = Some(self.borrow);
+        self.supply = compound_f64(self.supply - supply_rewards) + supply_rewards;
+        self.borrow = compound_f64(self.borrow + self.borrow_rewards) - self.borrow_rewards;
+        return true;
     }
 
-    fn calculate_supply(reserve: &Reserve) -> f64 {
-        let current_utilization = Self::calculate_utilization_ratio(reserve);
-        let borrow_apy = Self::calculate_borrow(reserve);
-        let supply_apy = current_utilization * borrow_apy;
-        return supply_apy;
+    /// Borrow APR on the piecewise linear curve of the reserve config, as the program calculates it
+    pub fn calculate_borrow(reserve: &Reserve) -> Result<Rate> {
+        return Ok(reserve.current_borrow_rate()?);
     }
 
-    fn calculate_utilization_ratio(reserve: &Reserve) -> f64 {
-        let borrowed_ammount = reserve.liquidity.borrowed_amount_wads.try_round_u64().unwrap();
-        let available_ammount = reserve.liquidity.available_amount;
-        let current_utilization = borrowed_ammount as f64 / (available_ammount + borrowed_ammount) as f64;
-        return current_utilization;
+    /// Borrow fee of the reserve config as a yearly rate. The fee is charged once on the borrowed
+    /// amount, for a loan held `BORROW_FEE_HOLDING_DAYS` that makes it this many times a year.
+    pub fn borrow_fee_rate(reserve: &Reserve) -> Result<Rate> {
+        let borrow_fee = Rate::from_scaled_val(reserve.config.fees.borrow_fee_wad);
+        return Ok(borrow_fee.try_mul(365u64)?.try_div(BORROW_FEE_HOLDING_DAYS)?);
     }
 
-    fn calculate_annual_tokens(reserve: &Reserve, asset_symbol: AssetSymbol, reward_stats: &serde_json::Value, external_reward_stats: &serde_json::Value, slnd_price: f64, mnde_price: f64) -> (f64, f64, String, String, Option<f64>) {
-        let program_config : ProgramConfig = serde_json::from_str(PRODUCTION_CONFIG_JSON).unwrap();
+    /// Gross and net supply APRs. Borrowers pay the interest and the borrow fee, shared among the
+    /// whole supply that is the gross APR. The fee goes to the protocol and the hosts
+    /// (`host_fee_percentage` of it), depositors only get the interest, the net APR.
+    pub fn calculate_supply(reserve: &Reserve) -> Result<(Rate, Rate)> {
+        let current_utilization = Self::calculate_utilization_ratio(reserve)?;
+        let borrow_apr = Self::calculate_borrow(reserve)?;
+        let gross_supply_apr = current_utilization.try_mul(borrow_apr.try_add(Self::borrow_fee_rate(reserve)?)?)?;
+        let net_supply_apr = current_utilization.try_mul(borrow_apr)?;
+        return Ok((gross_supply_apr, net_supply_apr));
+    }
+
+    /// Share of the liquidity that is borrowed, the borrowed amount including its accrued interest
+    fn calculate_utilization_ratio(reserve: &Reserve) -> Result<Rate> {
+        return Ok(reserve.liquidity.utilization_rate()?);
+    }
 
-        let mint_address = &program_config.assets.iter().find(|a| a.symbol == asset_symbol).unwrap().mint_address;
+    fn calculate_annual_tokens(reserve: &Reserve, reward_stats: &serde_json::Value, external_reward_stats: &serde_json::Value, slnd_price: f64, mnde_price: f64) -> Result<(Decimal, Decimal, String, String, Option<Decimal>)> {
+        let mint_address = reserve.liquidity.mint_pubkey.to_string();
+        let mint_address = mint_address.as_str();
 
         // Reward Rates
         let token_reward_stats = &reward_stats[mint_address];
         // TODO: Move this to ::from_value()
         let token_reward_stats = TokenRewardStat {
-            supply: serde_json::from_value(token_reward_stats["supply"].clone()).unwrap(), 
-            borrow: serde_json::from_value(token_reward_stats["borrow"].clone()).unwrap(), 
+            supply: serde_json::from_value(token_reward_stats["supply"].clone())?,
+            borrow: serde_json::from_value(token_reward_stats["borrow"].clone())?,
         };
 
         let supply_reward: (Decimal, String) = get_reward_rate_and_name(token_reward_stats.supply);
@@ -138,60 +176,290 @@ impl APY {
         // External Reward Rates
         let token_external_reward_stats = &external_reward_stats[mint_address];
         let token_external_reward_stats = TokenRewardStat {
-            supply: serde_json::from_value(token_external_reward_stats["supply"].clone()).unwrap(), 
-            borrow: serde_json::from_value(token_external_reward_stats["borrow"].clone()).unwrap(), 
+            supply: serde_json::from_value(token_external_reward_stats["supply"].clone())?,
+            borrow: serde_json::from_value(token_external_reward_stats["borrow"].clone())?,
         };
 
         let supply_external_reward: (Decimal, String) = get_reward_rate_and_name(token_external_reward_stats.supply);
         let borrow_external_reward: (Decimal, String) = get_reward_rate_and_name(token_external_reward_stats.borrow);
 
         if !supply_reward.1.is_empty() || !borrow_reward.1.is_empty() {
-            let market_price = (reserve.liquidity.market_price.to_scaled_val().unwrap() as f64) / 1_000_000_000_000_000_000f64;
-            let available_ammount = (reserve.liquidity.available_amount as f64).mul(market_price);
-            let borrowed_ammount = (reserve.liquidity.borrowed_amount_wads.try_round_u64().unwrap() as f64).mul(market_price);
-            let total_supply = available_ammount + borrowed_ammount;
-            let mint_decimals = reserve.liquidity.mint_decimals.into();
+            let total_supply = reserve.liquidity.total_supply()?;
+            let borrowed_ammount = reserve.liquidity.borrowed_amount_wads;
+            let slnd_price = decimal_from_f64(slnd_price);
+            let mnde_price = decimal_from_f64(mnde_price);
 
-            let mut supply_external_reward_apy : f64 = 0.0;
+            let mut supply_external_reward_apy = Decimal::zero();
             if supply_external_reward.0 != Decimal::zero() || borrow_external_reward.0 != Decimal::zero() {
-                supply_external_reward_apy = supply_external_reward.0.try_div(10_u64.pow(18)).unwrap().to_scaled_val().unwrap() as f64;
-                supply_external_reward_apy = supply_external_reward_apy * mnde_price / (total_supply as f64) * 10_f64.powi(mint_decimals);
+                supply_external_reward_apy = reward_apy(reserve, supply_external_reward.0, mnde_price, total_supply)?;
             }
-            let supply_external_reward_apy : Option<f64> = if supply_external_reward_apy == 0.0 { None } else { Some(supply_external_reward_apy) };
+            let supply_external_reward_apy : Option<Decimal> = if supply_external_reward_apy == Decimal::zero() { None } else { Some(supply_external_reward_apy) };
 
-            /* Borrow Rewards not available 
-            let mut borrow_external_reward_apy : f64 = 0.0;
+            /* Borrow Rewards not available
+            let mut borrow_external_reward_apy = Decimal::zero();
             if borrow_external_reward.0 != Decimal::zero() || borrow_external_reward.0 != Decimal::zero() {
-                borrow_external_reward_apy = borrow_external_reward.0.try_div(10_u64.pow(18)).unwrap().to_scaled_val().unwrap() as f64;
-                borrow_external_reward_apy = borrow_external_reward_apy * mnde_price / (borrowed_ammount as f64) * 10_f64.powi(mint_decimals);
+                borrow_external_reward_apy = reward_apy(reserve, borrow_external_reward.0, mnde_price, borrowed_ammount)?;
             }
-            let borrow_external_reward_apy : Option<f64> = if borrow_external_reward_apy == 0.0 { None } else { Some(borrow_external_reward_apy) };
+            let borrow_external_reward_apy : Option<Decimal> = if borrow_external_reward_apy == Decimal::zero() { None } else { Some(borrow_external_reward_apy) };
             */
 
-            let supply_reward_apy = supply_reward.0.try_div(10_u64.pow(18)).unwrap().to_scaled_val().unwrap() as f64;
-            let supply_reward_apy = supply_reward_apy * slnd_price / (total_supply as f64) * 10_f64.powi(mint_decimals);
-            let borrow_reward_apy = borrow_reward.0.try_div(10_u64.pow(18)).unwrap().to_scaled_val().unwrap() as f64;
-            let borrow_reward_apy = borrow_reward_apy * slnd_price / (borrowed_ammount as f64) * 10_f64.powi(mint_decimals);
+            let supply_reward_apy = reward_apy(reserve, supply_reward.0, slnd_price, total_supply)?;
+            let borrow_reward_apy = reward_apy(reserve, borrow_reward.0, slnd_price, borrowed_ammount)?;
 
-            return (supply_reward_apy, borrow_reward_apy, supply_reward.1, borrow_reward.1, supply_external_reward_apy);
-        } 
+            return Ok((supply_reward_apy, borrow_reward_apy, supply_reward.1, borrow_reward.1, supply_external_reward_apy));
+        }
+
+        return Ok((Decimal::zero(), Decimal::zero(), String::new(), String::new(), None));
+    }
+}
 
-        return (0f64, 0f64, String::new(), String::new(), None);
+/// Value of the tokens rewarded in a year at `reward_rate`, over the value of `amount` of the
+/// liquidity of `reserve` in its smallest unit. Nothing is rewarded on an empty amount.
+fn reward_apy(reserve: &Reserve, reward_rate: Decimal, token_price: Decimal, amount: Decimal) -> Result<Decimal> {
+    if amount == Decimal::zero() {
+        return Ok(Decimal::zero());
     }
+    let amount_value = amount.try_mul(reserve.liquidity.market_price)?;
+    let mint_scale = 10u64.checked_pow(reserve.liquidity.mint_decimals as u32).ok_or(Error::Decode("Reserve mint decimals overflow".to_string()))?;
+    return Ok(reward_rate.try_mul(token_price)?.try_mul(mint_scale)?.try_div(amount_value)?);
+}
+
+/// `APY::compound` in floating point, for the rates that are only stored as floats
+pub fn compound_f64(apr: f64) -> f64 {
+    let slots_per_year = SLOTS_PER_YEAR as f64;
+    return ((apr / slots_per_year).ln_1p() * slots_per_year).exp_m1();
+}
+
+/// Prices come from APIs as floats, they enter the fixed point math here
+pub fn decimal_from_f64(value: f64) -> Decimal {
+    return Decimal::from_scaled_val((value.max(0f64) * WAD as f64) as u128);
+}
+
+/// Rates and prices are only converted to floats once calculated, to be serialized
+pub fn decimal_to_f64(value: Decimal) -> Result<f64> {
+    return Ok(value.to_scaled_val()? as f64 / WAD as f64);
+}
+
+pub fn rate_to_f64(value: Rate) -> f64 {
+    return value.to_scaled_val() as f64 / WAD as f64;
 }
 
 fn get_reward_rate_and_name(token_reward_stats: Option<Reward>) -> (Decimal, String) {
     match token_reward_stats {
         Some(reward) => {
             match reward.reward_rates {
-                Some(reward_rate) => {
-                    let last_reward_rate = reward_rate.last().unwrap();
-                    let name = last_reward_rate.name.clone().unwrap_or_default();
-                    (last_reward_rate.reward_rate, name)
+                Some(reward_rate) => match reward_rate.last() {
+                    Some(last_reward_rate) => {
+                        let name = last_reward_rate.name.clone().unwrap_or_default();
+                        (last_reward_rate.reward_rate, name)
+                    },
+                    None => (Decimal::zero(), String::new())
                 },
                 None => (Decimal::zero(), String::new())
             }
         },
         None => (Decimal::zero(), String::new())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use solana_sdk::program_pack::Pack;
+    use spl_token_lending::state::{ReserveConfig, ReserveLiquidity};
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::stats::Stats;
+    use crate::utils::{Cluster, ProgramConfig};
+
+    /// Reserve of the main market fixture, SOL: 40% utilization, 4% borrow APR, 0.1% borrow fee and
+    /// USDC: 60% utilization, 6% borrow APR, 0.05% borrow fee
+    fn recorded_reserve(pubkey: &str) -> Reserve {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/main/reserves").join(format!("{}.bin", pubkey));
+        return Reserve::unpack_from_slice(&std::fs::read(path).unwrap()).unwrap();
+    }
+
+    fn recorded_reserves() -> Vec<Reserve> {
+        return vec![
+            recorded_reserve("8PbodeaosQP19SjYFx855UMqWxH2HynZLdBXmsrbac36"),
+            recorded_reserve("BgxfHJDzm44T7XG68MYKx7YisTjZu73tVovyZSjJMpmw"),
+        ];
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() <= 1e-9 * (1f64 + expected.abs()), "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn supply_gross_and_net() {
+        let reserves = recorded_reserves();
+        for (reserve, (utilization, borrow_apr, borrow_fee)) in reserves.iter().zip([(0.4, 0.04, 0.001), (0.6, 0.06, 0.0005)].iter()) {
+            let (gross, net) = APY::calculate_supply(reserve).unwrap();
+            let (gross, net) = (rate_to_f64(gross), rate_to_f64(net));
+            let yearly_fee = borrow_fee * 365f64 / BORROW_FEE_HOLDING_DAYS as f64;
+            assert_close(rate_to_f64(APY::borrow_fee_rate(reserve).unwrap()), yearly_fee);
+
+            // Depositors get the whole interest, the fee only goes into the gross APR
+            assert_close(net, utilization * borrow_apr);
+            assert_close(gross, utilization * (borrow_apr + yearly_fee));
+            assert_close(gross - net, utilization * yearly_fee);
+        }
+
+        // A reserve without a borrow fee has the same gross and net APRs
+        let mut reserve = reserves[0].clone();
+        reserve.config.fees.borrow_fee_wad = 0;
+        let (gross, net) = APY::calculate_supply(&reserve).unwrap();
+        assert_eq!(gross, net);
+    }
+
+    #[test]
+    fn rates_match_program() {
+        for (reserve, (utilization, borrow_apr)) in recorded_reserves().iter().zip([(0.4, 0.04), (0.6, 0.06)].iter()) {
+            assert_close(rate_to_f64(APY::calculate_utilization_ratio(reserve).unwrap()), *utilization);
+            assert_close(rate_to_f64(APY::calculate_borrow(reserve).unwrap()), *borrow_apr);
+
+            // At any utilization, the rates are the program's and the borrow APY is the interest
+            // it accrues on the borrows over a year
+            for percent in (0..=100u64).step_by(5) {
+                let mut sample = reserve.clone();
+                let total_supply = reserve.liquidity.total_supply().unwrap().try_floor_u64().unwrap();
+                sample.liquidity.available_amount = total_supply / 100 * (100 - percent);
+                sample.liquidity.borrowed_amount_wads = Decimal::from(total_supply / 100 * percent);
+
+                let utilization = APY::calculate_utilization_ratio(&sample).unwrap();
+                let borrow_apr = APY::calculate_borrow(&sample).unwrap();
+                assert_eq!(utilization, sample.liquidity.utilization_rate().unwrap());
+                assert_eq!(borrow_apr, sample.current_borrow_rate().unwrap());
+                let (_, supply_apr) = APY::calculate_supply(&sample).unwrap();
+                assert_eq!(supply_apr, utilization.try_mul(sample.current_borrow_rate().unwrap()).unwrap());
+
+                let mut accrued = sample.clone();
+                accrued.accrue_interest(sample.last_update.slot + SLOTS_PER_YEAR).unwrap();
+                let interest = accrued.liquidity.cumulative_borrow_rate_wads.try_div(sample.liquidity.cumulative_borrow_rate_wads).unwrap();
+                assert_close(decimal_to_f64(interest).unwrap() - 1f64, rate_to_f64(APY::compound(borrow_apr).unwrap()));
+            }
+        }
+    }
+
+    /// Reserve with `available` and `borrowed` liquidity in the smallest unit of its mint, the
+    /// borrow curve going from `min` to `optimal` at `optimal_utilization` and to `max` at 100%
+    fn sample_reserve(available: u64, borrowed: Decimal, mint_decimals: u8, price: f64, curve: (u8, u8, u8, u8)) -> Reserve {
+        let (optimal_utilization, min, optimal, max) = curve;
+        return Reserve {
+            liquidity: ReserveLiquidity {
+                mint_decimals,
+                available_amount: available,
+                borrowed_amount_wads: borrowed,
+                market_price: decimal_from_f64(price),
+                ..Default::default()
+            },
+            config: ReserveConfig {
+                optimal_utilization_rate: optimal_utilization,
+                min_borrow_rate: min,
+                optimal_borrow_rate: optimal,
+                max_borrow_rate: max,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+    }
+
+    fn sample_reserves() -> Vec<Reserve> {
+        return vec![
+            sample_reserve(1_200_000_000_000_000, Decimal::from(800_000_000_000_000u64), 9, 180.5, (80, 0, 8, 150)),
+            sample_reserve(30_000_000_000_000, Decimal::from_scaled_val(45_000_000_000_000_370_000_000_000_000_000), 6, 1.0001, (80, 0, 8, 100)),
+            sample_reserve(1_000, Decimal::from(9_000u64), 6, 0.53, (80, 0, 4, 150)),
+            sample_reserve(500_000_000, Decimal::zero(), 8, 60_000.0, (50, 2, 10, 30)),
+            sample_reserve(123_456_789, Decimal::from(987_654_321u64), 6, 2.5, (100, 1, 20, 20)),
+        ];
+    }
+
+    /// Utilization before the rates were calculated in fixed point
+    fn utilization_f64(reserve: &Reserve) -> f64 {
+        let borrowed = reserve.liquidity.borrowed_amount_wads.try_round_u64().unwrap();
+        return borrowed as f64 / (reserve.liquidity.available_amount + borrowed) as f64;
+    }
+
+    /// Borrow APR before the rates were calculated in fixed point
+    fn borrow_apr_f64(reserve: &Reserve) -> f64 {
+        let utilization = utilization_f64(reserve);
+        let config = &reserve.config;
+        let optimal_utilization = config.optimal_utilization_rate as f64 / 100f64;
+        let (min, optimal, max) = (config.min_borrow_rate as f64 / 100f64, config.optimal_borrow_rate as f64 / 100f64, config.max_borrow_rate as f64 / 100f64);
+        if optimal_utilization == 1f64 || utilization < optimal_utilization {
+            return utilization / optimal_utilization * (optimal - min) + min;
+        }
+        return (utilization - optimal_utilization) / (1f64 - optimal_utilization) * (max - optimal) + optimal;
+    }
+
+    /// Value of the liquidity of `reserve` before it was calculated in fixed point, in USD and
+    /// before the division by the mint scale
+    fn liquidity_value_f64(reserve: &Reserve) -> (f64, f64) {
+        let market_price = decimal_to_f64(reserve.liquidity.market_price).unwrap();
+        let available = reserve.liquidity.available_amount as f64 * market_price;
+        let borrowed = reserve.liquidity.borrowed_amount_wads.try_round_u64().unwrap() as f64 * market_price;
+        return (available, borrowed);
+    }
+
+    #[test]
+    fn compound_matches_f64() {
+        for percent in 0..=255u8 {
+            let apr = percent as f64 / 100f64;
+            let apy = rate_to_f64(APY::compound(Rate::from_percent(percent)).unwrap());
+            assert_close(apy, compound_f64(apr));
+            assert_close(apy, ((apr / SLOTS_PER_YEAR as f64).ln_1p() * SLOTS_PER_YEAR as f64).exp_m1());
+        }
+    }
+
+    #[test]
+    fn rates_match_f64() {
+        for reserve in sample_reserves().iter() {
+            assert_close(rate_to_f64(APY::calculate_utilization_ratio(reserve).unwrap()), utilization_f64(reserve));
+            assert_close(rate_to_f64(APY::calculate_borrow(reserve).unwrap()), borrow_apr_f64(reserve));
+            let (_, supply_apr) = APY::calculate_supply(reserve).unwrap();
+            assert_close(rate_to_f64(supply_apr), utilization_f64(reserve) * borrow_apr_f64(reserve));
+        }
+    }
+
+    #[test]
+    fn reward_apy_matches_f64() {
+        let slnd_price = 2.37;
+        for reserve in sample_reserves().iter() {
+            let (available, borrowed) = liquidity_value_f64(reserve);
+            let mint_scale = 10f64.powi(reserve.liquidity.mint_decimals as i32);
+            for tokens in [0u64, 1, 7_220_000, 18_050_000].iter() {
+                let reward_rate = Decimal::from(*tokens);
+                let price = decimal_from_f64(slnd_price);
+
+                let supply = reward_apy(reserve, reward_rate, price, reserve.liquidity.total_supply().unwrap()).unwrap();
+                assert_close(decimal_to_f64(supply).unwrap(), *tokens as f64 * slnd_price / (available + borrowed) * mint_scale);
+
+                let borrow = decimal_to_f64(reward_apy(reserve, reward_rate, price, reserve.liquidity.borrowed_amount_wads).unwrap()).unwrap();
+                if borrowed == 0f64 {
+                    assert_eq!(borrow, 0f64);
+                } else {
+                    assert_close(borrow, *tokens as f64 * slnd_price / borrowed * mint_scale);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn totals_match_f64() {
+        let program_config = ProgramConfig::from_json(Cluster::MAINNET.embedded_config().unwrap()).unwrap();
+        let market = program_config.market("main").unwrap();
+        let reserves = sample_reserves();
+
+        let (mut total_supplied, mut total_borrowed) = (0f64, 0f64);
+        for reserve in reserves.iter() {
+            let (available, borrowed) = liquidity_value_f64(reserve);
+            let mint_scale = 10f64.powi(reserve.liquidity.mint_decimals as i32);
+            total_supplied += (available + borrowed) / mint_scale;
+            total_borrowed += borrowed / mint_scale;
+        }
+
+        let reserves: Vec<Result<Reserve>> = reserves.into_iter().map(Ok).collect();
+        let stats = Stats::from_reserves(&reserves, market, &market.assets(), 2.37, 0.4).unwrap();
+        assert_close(stats.total_supplied, total_supplied);
+        assert_close(stats.total_borrowed, total_borrowed);
+    }
+}
@@ -0,0 +1,206 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::db::{self, APYDataPoint, DataType, SharedStorage};
+use crate::error::Result;
+use crate::snapshot::Snapshot;
+use crate::sources::FixtureSource;
+use crate::utils::{Market, ProgramConfig};
+
+/// Consecutive intervals of a series without any data point, from `from` until before `to`
+#[derive(Serialize, Debug, Clone)]
+pub struct Gap {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+impl Gap {
+    pub fn contains(&self, date: &DateTime<Utc>) -> bool {
+        return self.from <= *date && *date < self.to;
+    }
+}
+
+/// Start of the interval of `data_type` that `date` falls in. Intervals are counted from Monday
+/// 1970-01-05 so weeks start on Monday like the rolled up ones.
+fn interval_start(data_type: DataType, date: &DateTime<Utc>) -> DateTime<Utc> {
+    let origin = Utc.ymd(1970, 1, 5).and_hms(0, 0, 0);
+    let interval = data_type.interval().num_seconds();
+    let intervals = (*date - origin).num_seconds().div_euclid(interval);
+    return origin + Duration::seconds(intervals * interval);
+}
+
+/// Intervals of `data_type` fully within `from` and `to` that have no data point in `market`
+pub async fn find_gaps(storage: &SharedStorage, data_type: DataType, market: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Gap>> {
+    let interval = data_type.interval();
+    let mut start = interval_start(data_type, &from);
+    if start < from {
+        start = start + interval;
+    }
+
+    let data_points = storage.get_datapoints_between(data_type, market, start, to).await?;
+    let filled: BTreeSet<DateTime<Utc>> = data_points.iter().map(|data_point| interval_start(data_type, &data_point.date)).collect();
+
+    let mut gaps: Vec<Gap> = Vec::new();
+    let mut date = start;
+    while date + interval <= to {
+        if !filled.contains(&date) {
+            match gaps.last_mut() {
+                Some(gap) if gap.to == date => gap.to = date + interval,
+                _ => gaps.push(Gap { from: date, to: date + interval }),
+            }
+        }
+        date = date + interval;
+    }
+    return Ok(gaps);
+}
+
+/// Fills the gaps of the `data_type` series of `market` with the fixtures of `archive_dir`, one
+/// per directory as written by the record command. A data point marked as backfilled is saved for the
+/// first fixture recorded in every missing interval, then the coarser tiers are rolled up again.
+///
+/// Solana RPC nodes only serve the current state of an account, so the reserves at a past slot
+/// cannot be read back and the fixtures have to be archived when they are recorded.
+pub async fn backfill(storage: &SharedStorage, program_config: &ProgramConfig, market: &Market, data_type: DataType, archive_dir: &Path, now: DateTime<Utc>) -> Result<usize> {
+    let mut fixture_dirs: Vec<PathBuf> = fs::read_dir(archive_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir())
+        .collect();
+    fixture_dirs.sort();
+
+    let mut fixtures = Vec::new();
+    for fixture_dir in fixture_dirs {
+        let fixture = FixtureSource::new(fixture_dir);
+        match fixture.recorded_at()? {
+            Some(recorded_at) => fixtures.push((recorded_at, fixture)),
+            None => warn!("Fixture {} has no recording date and is skipped", fixture.dir().display()),
+        }
+    }
+    fixtures.sort_by_key(|(recorded_at, _)| *recorded_at);
+    let from = match fixtures.first() {
+        Some((recorded_at, _)) => interval_start(data_type, recorded_at),
+        None => {
+            info!("No fixture to backfill from in {}", archive_dir.display());
+            return Ok(0);
+        },
+    };
+
+    let gaps = find_gaps(storage, data_type, &market.name, from, now).await?;
+    let mut filled = HashSet::new();
+    let mut backfilled: Vec<DateTime<Utc>> = Vec::new();
+    for (recorded_at, fixture) in fixtures.iter() {
+        if !gaps.iter().any(|gap| gap.contains(recorded_at)) || !filled.insert(interval_start(data_type, recorded_at)) {
+            continue;
+        }
+        let snapshot = match Snapshot::from_source(fixture, program_config, market).await {
+            Ok(snapshot) if !snapshot.apys.apys.is_empty() => snapshot,
+            Ok(_) => {
+                warn!("Fixture {} has no reserve of {} market", fixture.dir().display(), market.name);
+                continue;
+            },
+            Err(e) => {
+                error!("Could not read fixture {}: {}", fixture.dir().display(), e);
+                continue;
+            },
+        };
+
+        let mut data_point = APYDataPoint::from_snapshot(&snapshot, data_type);
+        data_point.date = *recorded_at;
+        // Fixtures recorded before the slot was stored report slot 0
+        data_point.slot = data_point.slot.filter(|slot| *slot > 0);
+        data_point.backfilled = true;
+        storage.insert_datapoint(&data_point).await?;
+        backfilled.push(*recorded_at);
+    }
+    info!("{} {} data points of {} market backfilled from {}", backfilled.len(), data_type, market.name, archive_dir.display());
+
+    if let Some(since) = backfilled.first() {
+        db::rollup_since(storage, &market.name, *since, now).await?;
+    }
+    return Ok(backfilled.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{data_point, TempStorage};
+    use crate::db::Storage;
+    use crate::utils::Cluster;
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        return Utc.ymd(2021, 11, 3).and_hms(hour, minute, 0);
+    }
+
+    /// Copy of the main market fixture in `archive_dir`, recorded at `recorded_at`
+    fn archive_fixture(archive_dir: &Path, recorded_at: DateTime<Utc>) {
+        let source = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/main");
+        let dir = archive_dir.join(recorded_at.timestamp().to_string());
+        fs::create_dir_all(dir.join("reserves")).unwrap();
+        for path in ["reward-stats.json", "external-reward-stats.json", "prices.json", "slot.json"].iter() {
+            fs::copy(source.join(path), dir.join(path)).unwrap();
+        }
+        for entry in fs::read_dir(source.join("reserves")).unwrap() {
+            let path = entry.unwrap().path();
+            fs::copy(&path, dir.join("reserves").join(path.file_name().unwrap())).unwrap();
+        }
+        fs::write(dir.join("date.json"), serde_json::to_string(&recorded_at).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn intervals_start_on_monday() {
+        assert_eq!(interval_start(DataType::MINUTE, &at(11, 29)), at(11, 0));
+        assert_eq!(interval_start(DataType::MINUTE, &at(11, 30)), at(11, 30));
+        assert_eq!(interval_start(DataType::HOUR, &at(11, 59)), at(11, 0));
+        assert_eq!(interval_start(DataType::DAY, &at(11, 59)), at(0, 0));
+        // 2021-11-03 is a Wednesday
+        assert_eq!(interval_start(DataType::WEEK, &at(11, 59)), Utc.ymd(2021, 11, 1).and_hms(0, 0, 0));
+        assert_eq!(interval_start(DataType::WEEK, &Utc.ymd(1970, 1, 1).and_hms(0, 0, 0)), Utc.ymd(1969, 12, 29).and_hms(0, 0, 0));
+    }
+
+    #[test]
+    fn backfill_fills_the_gaps() {
+        let temp = TempStorage::new("backfill");
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let program_config = ProgramConfig::from_json(Cluster::MAINNET.embedded_config().unwrap()).unwrap();
+        let market = program_config.market("main").unwrap().clone();
+
+        // Nothing was saved at 11:00 and 11:30
+        runtime.block_on(temp.storage.insert_datapoints(&[
+            data_point(at(10, 0), DataType::MINUTE, 0.05),
+            data_point(at(10, 30), DataType::MINUTE, 0.05),
+            data_point(at(12, 0), DataType::MINUTE, 0.05),
+        ])).unwrap();
+
+        // Intervals that are not complete by the end of the range are left out
+        let gaps = runtime.block_on(find_gaps(&temp.storage, DataType::MINUTE, "main", at(9, 45), at(12, 45))).unwrap();
+        let gaps: Vec<(DateTime<Utc>, DateTime<Utc>)> = gaps.iter().map(|gap| (gap.from, gap.to)).collect();
+        assert_eq!(gaps, vec![(at(11, 0), at(12, 0))]);
+
+        // Only the first fixture of every missing interval is saved
+        let archive_dir = std::env::temp_dir().join(format!("solend-apy-bot-archive-{}", std::process::id()));
+        for recorded_at in [at(10, 10), at(11, 5), at(11, 10), at(11, 40)].iter() {
+            archive_fixture(&archive_dir, *recorded_at);
+        }
+        let count = runtime.block_on(backfill(&temp.storage, &program_config, &market, DataType::MINUTE, &archive_dir, at(12, 30)));
+        fs::remove_dir_all(&archive_dir).unwrap();
+        assert_eq!(count.unwrap(), 2);
+
+        let data_points = runtime.block_on(temp.storage.get_datapoints_between(DataType::MINUTE, "main", at(0, 0), at(23, 0))).unwrap();
+        let dates: Vec<(DateTime<Utc>, bool)> = data_points.iter().map(|data_point| (data_point.date, data_point.backfilled)).collect();
+        assert_eq!(dates, vec![(at(10, 0), false), (at(10, 30), false), (at(11, 5), true), (at(11, 40), true), (at(12, 0), false)]);
+        assert_eq!(data_points[2].slot, Some(110_000_000));
+        assert_eq!(data_points[2].apys.len(), 2);
+
+        let gaps = runtime.block_on(find_gaps(&temp.storage, DataType::MINUTE, "main", at(10, 0), at(12, 30))).unwrap();
+        assert!(gaps.is_empty());
+
+        // The hours are rolled up again from the first backfilled one, the one in progress is left out
+        let hours = runtime.block_on(temp.storage.get_datapoints_between(DataType::HOUR, "main", at(0, 0), at(23, 0))).unwrap();
+        assert_eq!(hours.iter().map(|data_point| (data_point.date, data_point.backfilled)).collect::<Vec<_>>(), vec![(at(11, 0), true)]);
+    }
+}
@@ -26,18 +26,18 @@ pub struct TwitterBot {
 }
 
 impl TwitterBot {
-    pub fn from_config(config: utils::Config) -> Self {
-        let consumer_key = config.twitter.consumer_key;
-        let consumer_secret = config.twitter.consumer_secret;
+    pub fn from_config(config: &utils::TwitterConfig) -> Self {
+        let consumer_key = config.consumer_key.clone();
+        let consumer_secret = config.consumer_secret.clone();
         let consumer = KeyPair::new(consumer_key, consumer_secret);
 
-        let access_key = config.twitter.access_key;
-        let access_secret = config.twitter.access_secret;
+        let access_key = config.access_key.clone();
+        let access_secret = config.access_secret.clone();
         let access = KeyPair::new(access_key, access_secret);
 
         let token = Token::Access {
-            consumer: consumer,
-            access: access,
+            consumer,
+            access,
         };
 
         return Self { token };
@@ -52,7 +52,7 @@ impl TwitterBot {
         info!("Tweet created for {:?}", image_path);
 
         // Tweet
-        tweet.send(&self.token).await.unwrap();
+        tweet.send(&self.token).await?;
         info!("Tweet sent");
         return Ok(());
     }
@@ -63,8 +63,8 @@ pub struct ScreenshotBot {
 }
 
 impl ScreenshotBot {
-    pub fn from_config(config: utils::Config) -> Result<Self, failure::Error> {
-        let url = format!("http://{}:{}", config.server.host, config.server.port);
+    pub fn from_config(config: &utils::Serverconfig) -> Result<Self, failure::Error> {
+        let url = format!("http://{}:{}", config.host, config.port);
         return Ok(Self { url });
     }
     
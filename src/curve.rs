@@ -0,0 +1,225 @@
+use serde::{Deserialize, Serialize};
+use spl_token_lending::math::{Decimal, TryMul};
+use spl_token_lending::state::Reserve;
+
+use crate::apy::{decimal_from_f64, rate_to_f64, APY};
+use crate::error::{Error, Result};
+use crate::snapshot::Snapshot;
+use crate::utils::{AssetSymbol, Market, ProgramConfig};
+
+/// Interest rates of a reserve at a given utilization, without the rewards
+#[derive(Serialize, Debug, Clone)]
+pub struct CurvePoint {
+    pub utilization: f64,
+    pub supply: f64,
+    pub borrow: f64,
+    pub supply_apr: f64,
+    pub borrow_apr: f64,
+}
+
+/// Parameters of the reserve config the interest rate curve is drawn from, the borrow rates as APRs
+#[derive(Serialize, Debug, Clone)]
+pub struct CurveParams {
+    pub optimal_utilization: f64,
+    pub min_borrow_rate: f64,
+    pub optimal_borrow_rate: f64,
+    pub max_borrow_rate: f64,
+}
+
+impl CurveParams {
+    pub fn from_reserve(reserve: &Reserve) -> Self {
+        let config = &reserve.config;
+        return Self {
+            optimal_utilization: config.optimal_utilization_rate as f64 / 100f64,
+            min_borrow_rate: config.min_borrow_rate as f64 / 100f64,
+            optimal_borrow_rate: config.optimal_borrow_rate as f64 / 100f64,
+            max_borrow_rate: config.max_borrow_rate as f64 / 100f64,
+        };
+    }
+}
+
+/// Interest rate curve of a reserve sampled at every percent of utilization
+#[derive(Serialize, Debug, Clone)]
+pub struct RateCurve {
+    pub market: String,
+    pub asset: AssetSymbol,
+    /// Current utilization of the reserve
+    pub utilization: f64,
+    #[serde(flatten)]
+    pub params: CurveParams,
+    pub points: Vec<CurvePoint>,
+}
+
+impl RateCurve {
+    pub fn from_reserve(reserve: &Reserve, market: &Market, asset_symbol: &AssetSymbol) -> Result<Self> {
+        let mut points = Vec::new();
+        for percent in 0..=100u8 {
+            // Only the share of the liquidity that is borrowed matters to the rates, so any
+            // amounts split in these proportions will do
+            let mut sample = reserve.clone();
+            sample.liquidity.available_amount = (100 - percent) as u64;
+            sample.liquidity.borrowed_amount_wads = Decimal::from(percent as u64);
+
+            let borrow_apr = APY::calculate_borrow(&sample)?;
+            let (_, supply_apr) = APY::calculate_supply(&sample)?;
+            points.push(CurvePoint {
+                utilization: percent as f64 / 100f64,
+                supply: rate_to_f64(APY::compound(supply_apr)?),
+                borrow: rate_to_f64(APY::compound(borrow_apr)?),
+                supply_apr: rate_to_f64(supply_apr),
+                borrow_apr: rate_to_f64(borrow_apr),
+            });
+        }
+
+        return Ok(Self {
+            market: market.name.clone(),
+            asset: asset_symbol.clone(),
+            utilization: rate_to_f64(reserve.liquidity.utilization_rate()?),
+            params: CurveParams::from_reserve(reserve),
+            points,
+        });
+    }
+}
+
+/// Query string of the simulate route, amounts in tokens of the asset
+#[derive(Deserialize, Debug, Default)]
+pub struct SimulationParams {
+    pub deposit: Option<f64>,
+    pub borrow: Option<f64>,
+}
+
+/// APYs of a reserve as they are and as they would be right after a deposit and a borrow, the
+/// rewards spread over the new supply and borrows
+#[derive(Serialize, Debug, Clone)]
+pub struct Simulation {
+    pub market: String,
+    pub asset: AssetSymbol,
+    pub deposit: f64,
+    pub borrow: f64,
+    pub current: APY,
+    pub projected: APY,
+}
+
+impl Simulation {
+    pub fn from_snapshot(snapshot: &Snapshot, reserve: &Reserve, program_config: &ProgramConfig, market: &Market, asset_symbol: &AssetSymbol, params: &SimulationParams) -> Result<Self> {
+        let deposit = params.deposit.unwrap_or(0f64);
+        let borrow = params.borrow.unwrap_or(0f64);
+
+        // The deposit is made first, so its liquidity can be borrowed
+        let mut projected = reserve.clone();
+        projected.liquidity.deposit(to_native_amount(reserve, deposit, "deposit")?)?;
+        let borrow_amount = to_native_amount(reserve, borrow, "borrow")?;
+        if borrow_amount > projected.liquidity.available_amount {
+            return Err(Error::InvalidRequest(format!("borrow of {} {} exceeds the available liquidity", borrow, asset_symbol)));
+        }
+        projected.liquidity.borrow(Decimal::from(borrow_amount))?;
+
+        let apy = |reserve: &Reserve| APY::from_reserve(
+            reserve, program_config, market, asset_symbol.clone(), &snapshot.reward_stats,
+            &snapshot.external_reward_stats, snapshot.stats.slnd_price, snapshot.stats.mnde_price,
+        );
+        return Ok(Self {
+            market: market.name.clone(),
+            asset: asset_symbol.clone(),
+            deposit,
+            borrow,
+            current: apy(reserve)?,
+            projected: apy(&projected)?,
+        });
+    }
+}
+
+/// Converts an amount of tokens to the smallest unit of the mint of `reserve`
+fn to_native_amount(reserve: &Reserve, amount: f64, name: &str) -> Result<u64> {
+    if !amount.is_finite() || amount < 0f64 {
+        return Err(Error::InvalidRequest(format!("{} must be an amount of tokens, 0 or more", name)));
+    }
+    let mint_scale = 10u64.checked_pow(reserve.liquidity.mint_decimals as u32).ok_or(Error::Decode("Reserve mint decimals overflow".to_string()))?;
+    return decimal_from_f64(amount).try_mul(mint_scale)?.try_floor_u64()
+        .map_err(|_| Error::InvalidRequest(format!("{} of {} tokens is too large", name, amount)));
+}
+
+#[cfg(test)]
+mod tests {
+    use spl_token_lending::math::WAD;
+    use spl_token_lending::state::SLOTS_PER_YEAR;
+
+    use super::*;
+    use crate::snapshot::testing::take_snapshot;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() <= 1e-9 * (1f64 + expected.abs()), "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn curve_follows_the_reserve_config() {
+        let (snapshot, program_config) = take_snapshot();
+        let market = program_config.market("main").unwrap();
+        for apy in snapshot.apys.apys.iter() {
+            let reserve = snapshot.reserve(market.reserve(&apy.asset).unwrap()).unwrap();
+            let curve = RateCurve::from_reserve(reserve, market, &apy.asset).unwrap();
+            assert_eq!(curve.points.len(), 101);
+            assert_close(curve.utilization, apy.utilization.unwrap());
+
+            let optimal = &curve.points[reserve.config.optimal_utilization_rate as usize];
+            assert_close(optimal.utilization, curve.params.optimal_utilization);
+            assert_close(optimal.borrow_apr, curve.params.optimal_borrow_rate);
+            assert_close(curve.points[0].borrow_apr, curve.params.min_borrow_rate);
+            assert_close(curve.points[100].borrow_apr, curve.params.max_borrow_rate);
+
+            for (previous, point) in curve.points.iter().zip(curve.points.iter().skip(1)) {
+                assert!(point.borrow_apr >= previous.borrow_apr, "{} borrow APR falls at {}", apy.asset, point.utilization);
+                assert!(point.supply_apr >= previous.supply_apr, "{} supply APR falls at {}", apy.asset, point.utilization);
+                // The slot rate is rounded down to the wad as in the program, up to a wad per slot
+                let rounding = SLOTS_PER_YEAR as f64 / WAD as f64;
+                assert!(point.borrow + rounding >= point.borrow_apr && point.supply + rounding >= point.supply_apr, "{:?}", point);
+                assert_close(point.supply_apr, point.utilization * point.borrow_apr);
+            }
+        }
+    }
+
+    #[test]
+    fn simulation_without_change() {
+        let (snapshot, program_config) = take_snapshot();
+        let market = program_config.market("main").unwrap();
+        for apy in snapshot.apys.apys.iter() {
+            let reserve = snapshot.reserve(market.reserve(&apy.asset).unwrap()).unwrap();
+            let params = SimulationParams { deposit: Some(0f64), borrow: None };
+            let simulation = Simulation::from_snapshot(&snapshot, reserve, &program_config, market, &apy.asset, &params).unwrap();
+            let (current, projected) = (&simulation.current, &simulation.projected);
+            assert_close(current.supply, apy.supply);
+            assert_close(current.borrow, apy.borrow);
+            for (current, projected) in [
+                (current.supply, projected.supply),
+                (current.borrow, projected.borrow),
+                (current.supply_rewards, projected.supply_rewards),
+                (current.borrow_rewards, projected.borrow_rewards),
+                (current.utilization.unwrap(), projected.utilization.unwrap()),
+            ].iter() {
+                assert_eq!(current, projected);
+            }
+        }
+    }
+
+    #[test]
+    fn simulation_of_a_deposit_and_a_borrow() {
+        let (snapshot, program_config) = take_snapshot();
+        let market = program_config.market("main").unwrap();
+        let sol = &snapshot.apys.apys[0];
+        let reserve = snapshot.reserve(market.reserve(&sol.asset).unwrap()).unwrap();
+
+        // 200k SOL deposited into the 2M supplied, 800k borrowed
+        let params = SimulationParams { deposit: Some(200_000f64), borrow: None };
+        let simulation = Simulation::from_snapshot(&snapshot, reserve, &program_config, market, &sol.asset, &params).unwrap();
+        assert_close(simulation.projected.utilization.unwrap(), 800_000f64 / 2_200_000f64);
+        assert!(simulation.projected.supply < simulation.current.supply);
+        assert!(simulation.projected.supply_rewards < simulation.current.supply_rewards);
+
+        // 1.2M SOL are available, the deposit comes first
+        let params = SimulationParams { deposit: Some(100f64), borrow: Some(1_200_100f64) };
+        let simulation = Simulation::from_snapshot(&snapshot, reserve, &program_config, market, &sol.asset, &params).unwrap();
+        assert_close(simulation.projected.utilization.unwrap(), 1.0);
+        let params = SimulationParams { deposit: None, borrow: Some(1_200_001f64) };
+        assert!(Simulation::from_snapshot(&snapshot, reserve, &program_config, market, &sol.asset, &params).is_err());
+    }
+}
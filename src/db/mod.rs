@@ -0,0 +1,280 @@
+pub mod mongo;
+pub mod rollup;
+pub mod sqlite;
+
+pub use self::mongo::*;
+pub use self::rollup::*;
+pub use self::sqlite::*;
+
+use async_trait::async_trait;
+use chrono::Datelike;
+use chrono::offset::Utc;
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::TimeZone;
+use log::warn;
+use serde::{Serialize, Deserialize};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::fmt;
+
+use crate::config::Config;
+use crate::apy::APY;
+use crate::snapshot::Snapshot;
+use crate::stats::Stats;
+use crate::error::{Error, Result};
+use crate::history::{AssetHistory, Bucket, HistoryPoint, HistoryQuery, Metric};
+use crate::AssetSymbol;
+use crate::utils::default_market;
+
+/// Persistence of the data points saved by the bot
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn insert_datapoint(&self, data_point: &APYDataPoint) -> Result<()>;
+    /// Data points of `data_type` in `market` dated from `from` until before `to`, oldest first
+    async fn get_datapoints_between(&self, data_type: DataType, market: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<APYDataPoint>>;
+    /// Date of the latest data point of `data_type` in `market`
+    async fn get_last_date(&self, data_type: DataType, market: &str) -> Result<Option<DateTime<Utc>>>;
+    /// Deletes the data points of `data_type` dated from `from` until before `to`, in every market
+    /// unless one is given, and returns how many were deleted
+    async fn delete_datapoints(&self, data_type: DataType, market: Option<&str>, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<u64>;
+    /// Replaces the data points of the type and market of `data_point` dated from its date until
+    /// before `to` with it, without a moment where there is none
+    async fn replace_datapoints(&self, data_point: &APYDataPoint, to: DateTime<Utc>) -> Result<()>;
+    /// Averages of the data points matching `query`, per asset and bucket
+    async fn get_history(&self, query: &HistoryQuery) -> Result<Vec<AssetHistory>>;
+    /// Brings data points stored by older versions to the current format
+    async fn migrate(&self) -> Result<()>;
+
+    /// Inserts many data points at once, one by one unless the backend can batch them
+    async fn insert_datapoints(&self, data_points: &[APYDataPoint]) -> Result<()> {
+        for data_point in data_points {
+            self.insert_datapoint(data_point).await?;
+        }
+        return Ok(());
+    }
+
+    /// Stores the APYs and stats of `snapshot` as a single data point
+    async fn save_snapshot(&self, snapshot: &Snapshot, data_type: DataType) -> Result<()> {
+        for asset_error in snapshot.apys.errors.iter() {
+            warn!("{} is missing from the data point: {}", asset_error.asset, asset_error.error);
+        }
+        return self.insert_datapoint(&APYDataPoint::from_snapshot(snapshot, data_type)).await;
+    }
+
+    /// Daily averages of the hourly data points of `market` over the last `days_back` days
+    async fn get_daily_datapoints_as_avg(&self, market: &str, days_back: Duration) -> Result<Vec<APYDataPointAggregatedString>> {
+        let (from_date, today) = daily_range(days_back)?;
+        let query = HistoryQuery {
+            market: market.to_string(),
+            assets: Vec::new(),
+            metrics: vec![Metric::SUPPLY, Metric::BORROW],
+            from: from_date,
+            to: today,
+            bucket: Bucket::DAY,
+            data_type: DataType::HOUR,
+        };
+        let history = self.get_history(&query).await?;
+        return Ok(history.into_iter().map(|asset_history| APYDataPointAggregatedString {
+            name: asset_history.asset,
+            supply: chart_series(&asset_history.points, Metric::SUPPLY),
+            borrow: chart_series(&asset_history.points, Metric::BORROW),
+        }).collect());
+    }
+}
+
+/// `[date, mean, low, high]` of every bucket with a value of `metric`
+fn chart_series(points: &[HistoryPoint], metric: Metric) -> Vec<Vec<String>> {
+    return points.iter()
+        .filter_map(|point| point.values.get(&metric).cloned().flatten().map(|value| {
+            let (low, high) = point.stats.get(&metric).map(|summary| (summary.low, summary.high)).unwrap_or((value, value));
+            vec![point.date.to_string(), value.to_string(), low.to_string(), high.to_string()]
+        }))
+        .collect();
+}
+
+pub type SharedStorage = dyn Storage;
+
+/// Opens the storage backend selected in the config, MongoDB unless stated otherwise
+pub async fn storage_from_config(config: &Config) -> Result<Arc<SharedStorage>> {
+    let backend = match &config.storage.backend {
+        Some(backend) => StorageBackend::from_str(backend).map_err(Error::Config)?,
+        None => StorageBackend::MONGODB,
+    };
+    match backend {
+        StorageBackend::MONGODB => {
+            let dbconfig = config.mongodb.as_ref().ok_or(Error::Config("MONGODB.* is required by the mongodb storage".to_string()))?;
+            Ok(Arc::new(MongoStorage::from_config(dbconfig).await?))
+        },
+        StorageBackend::SQLITE => Ok(Arc::new(SqliteStorage::open(&config.storage.path())?)),
+    }
+}
+
+/// Range covered by the daily averages: the last `days_back` full days up to today at 00:00
+pub fn daily_range(days_back: Duration) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let now = Utc::now();
+    let today= Utc.ymd(now.year(), now.month(), now.day()).and_hms(0, 00, 00);
+    let from_date = today.checked_sub_signed(days_back)
+        .and_then(|date| date.checked_add_signed(Duration::seconds(1)))
+        .ok_or(Error::Decode(format!("Invalid date range of {} days", days_back.num_days())))?;
+    return Ok((from_date, today));
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StorageBackend {
+    MONGODB,
+    SQLITE,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "MONGODB" | "mongodb" => Ok(StorageBackend::MONGODB),
+            "SQLITE" | "sqlite" => Ok(StorageBackend::SQLITE),
+            _ => Err(format!("'{}' is not a valid value for StorageBackend", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct APYDataPoint {
+    pub date: DateTime<Utc>,
+    #[serde(default = "default_market")]
+    pub market: String,
+    pub data_type: DataType,
+    /// Slot the reserves were read at, missing in data points stored before snapshots
+    #[serde(default)]
+    pub slot: Option<u64>,
+    pub apys: Vec<APY>,
+    #[serde(default)]
+    pub stats: Option<Stats>,
+    /// Saved after the fact from an archived snapshot, rather than at its date
+    #[serde(default)]
+    pub backfilled: bool,
+}
+
+impl APYDataPoint {
+    pub fn from_snapshot(snapshot: &Snapshot, data_type: DataType) -> Self {
+        return Self {
+            date: snapshot.last_updated,
+            market: snapshot.market.clone(),
+            data_type,
+            slot: Some(snapshot.slot),
+            apys: snapshot.apys.apys.clone(),
+            stats: Some(snapshot.stats.clone()),
+            backfilled: false,
+        };
+    }
+}
+
+/// Daily supply and borrow series of an asset, as returned by `chart_series`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct APYDataPointAggregatedString {
+    pub name: AssetSymbol,
+    pub supply: Vec<Vec<String>>,
+    pub borrow: Vec<Vec<String>>,
+}
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub enum DataType {
+    MINUTE,
+    HOUR,
+    DAY,
+    WEEK,
+}
+
+impl DataType {
+    /// Time between two data points of the tier, the MINUTE ones are saved every 30 minutes
+    pub fn interval(&self) -> Duration {
+        return match self {
+            DataType::MINUTE => Duration::minutes(30),
+            DataType::HOUR => Duration::hours(1),
+            DataType::DAY => Duration::days(1),
+            DataType::WEEK => Duration::weeks(1),
+        };
+    }
+}
+
+impl FromStr for DataType {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "MINUTE"| "minute" => Ok(DataType::MINUTE),
+            "HOUR"| "hour" => Ok(DataType::HOUR),
+            "DAY" | "day" => Ok(DataType::DAY),
+            "WEEK" | "week" => Ok(DataType::WEEK),
+            _ => Err(format!("'{}' is not a valid value for DataType", s)),
+        }
+    }
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Storage and data points shared by the tests of the modules built on top of the storage
+#[cfg(test)]
+pub mod testing {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// SQLite storage in a file of its own, removed when dropped
+    pub struct TempStorage {
+        pub storage: SqliteStorage,
+        path: PathBuf,
+    }
+
+    impl TempStorage {
+        pub fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("solend-apy-bot-{}-{}.sqlite", name, std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            return Self { storage: SqliteStorage::open(&path).unwrap(), path };
+        }
+    }
+
+    impl Drop for TempStorage {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    pub fn apy(asset: &str, supply: f64, borrow: f64) -> APY {
+        return APY {
+            market: "main".to_string(),
+            asset: AssetSymbol(asset.to_string()),
+            name: asset.to_string(),
+            price: 1.0,
+            supply,
+            borrow,
+            supply_rewards: 0.01,
+            borrow_rewards: 0.02,
+            weight_supply: "SLND".to_string(),
+            weight_borrow: "SLND".to_string(),
+            mnde_supply_rewards: None,
+            utilization: Some(0.5),
+            supply_apr: Some(supply),
+            borrow_apr: Some(borrow),
+            supply_gross: Some(supply),
+        };
+    }
+
+    /// Data point of the main market with the SOL and USDC APYs
+    pub fn data_point(date: DateTime<Utc>, data_type: DataType, supply: f64) -> APYDataPoint {
+        return APYDataPoint {
+            date,
+            market: "main".to_string(),
+            data_type,
+            slot: Some(100),
+            apys: vec![apy("SOL", supply, supply * 2f64), apy("USDC", supply / 2f64, supply)],
+            stats: None,
+            backfilled: false,
+        };
+    }
+}
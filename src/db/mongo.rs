@@ -0,0 +1,321 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::info;
+use mongodb::{
+    Client,
+    Collection,
+    Database,
+    bson::{self, doc, to_document, serde_helpers::chrono_datetime_as_bson_datetime, Bson, Document},
+    options::{ClientOptions, FindOneAndReplaceOptions, FindOneOptions, FindOptions, ReturnDocument}
+};
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::apy::APY;
+use crate::config::DatabaseConfig;
+use crate::db::{APYDataPoint, DataType, Storage};
+use crate::error::{Error, Result};
+use crate::history::{AssetHistory, Bucket, HistoryQuery, Sample};
+use crate::stats::Stats;
+use crate::utils::{default_market, AssetSymbol, DEFAULT_MARKET};
+
+/// Document stored in the `data_*` collections, with the date as a BSON date. Documents written
+/// before hold the date as a string until `migrate` converts them, both are read.
+#[derive(Serialize, Deserialize)]
+struct MongoDataPoint {
+    #[serde(serialize_with = "chrono_datetime_as_bson_datetime::serialize", deserialize_with = "deserialize_date")]
+    date: DateTime<Utc>,
+    #[serde(default = "default_market")]
+    market: String,
+    data_type: DataType,
+    #[serde(default)]
+    slot: Option<u64>,
+    apys: Vec<APY>,
+    #[serde(default)]
+    stats: Option<Stats>,
+    #[serde(default)]
+    backfilled: bool,
+}
+
+impl From<&APYDataPoint> for MongoDataPoint {
+    fn from(item: &APYDataPoint) -> Self {
+        return MongoDataPoint {
+            date: item.date,
+            market: item.market.clone(),
+            data_type: item.data_type,
+            slot: item.slot,
+            apys: item.apys.clone(),
+            stats: item.stats.clone(),
+            backfilled: item.backfilled,
+        };
+    }
+}
+
+impl From<MongoDataPoint> for APYDataPoint {
+    fn from(item: MongoDataPoint) -> Self {
+        return APYDataPoint {
+            date: item.date,
+            market: item.market,
+            data_type: item.data_type,
+            slot: item.slot,
+            apys: item.apys,
+            stats: item.stats,
+            backfilled: item.backfilled,
+        };
+    }
+}
+
+fn deserialize_date<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<DateTime<Utc>, D::Error> {
+    match Bson::deserialize(deserializer)? {
+        Bson::DateTime(date) => Ok(date.to_chrono()),
+        Bson::String(date) => parse_legacy_date(&date).map_err(de::Error::custom),
+        date => Err(de::Error::custom(format!("Invalid date {}", date))),
+    }
+}
+
+/// Data points stored before markets were tracked belong to the main pool
+fn market_filter(market: &str) -> Document {
+    return if market == DEFAULT_MARKET { doc! { "$in": [market, Bson::Null] } } else { doc! { "$eq": market } };
+}
+
+/// Date parts of the start of the bucket `$date` falls in, weeks start on Monday
+fn bucket_date_parts(bucket: Bucket) -> Document {
+    return match bucket {
+        Bucket::HOUR => doc! { "year": { "$year": "$date" }, "month": { "$month": "$date" }, "day": { "$dayOfMonth": "$date" }, "hour": { "$hour": "$date" } },
+        Bucket::DAY => doc! { "year": { "$year": "$date" }, "month": { "$month": "$date" }, "day": { "$dayOfMonth": "$date" } },
+        Bucket::WEEK => doc! { "isoWeekYear": { "$isoWeekYear": "$date" }, "isoWeek": { "$isoWeek": "$date" } },
+        Bucket::MONTH => doc! { "year": { "$year": "$date" }, "month": { "$month": "$date" } },
+    };
+}
+
+/// Dates used to be stored as the RFC 3339 string chrono serializes to
+fn parse_legacy_date(date: &str) -> Result<DateTime<Utc>> {
+    return DateTime::parse_from_rfc3339(date)
+        .map(|date| date.with_timezone(&Utc))
+        .map_err(|e| Error::Decode(format!("Invalid date '{}': {}", date, e)));
+}
+
+/// Keeps the data points in a MongoDB Atlas cluster, one `data_<data type>` collection per data type
+pub struct MongoStorage {
+    client: Client,
+    database_name: &'static str,
+}
+
+impl MongoStorage {
+    pub async fn from_config(dbconfig: &DatabaseConfig) -> Result<Self> {
+        // Parse a connection string into an options struct.
+        let url = format!(
+            "mongodb+srv://{}:{}@{}/{}?retryWrites=true&w=majority",
+            dbconfig.user, dbconfig.pass, dbconfig.server, dbconfig.dbname
+        );
+        let mut client_options = ClientOptions::parse(url).await?;
+        client_options.app_name = Some("solend-apy-bot".to_string());
+
+        // Get a handle to the deployment.
+        let client = Client::with_options(client_options)?;
+
+        let database_name = "solend-database";
+
+        return Ok(Self {
+            client,
+            database_name,
+        });
+    }
+
+    fn database(&self) -> Database {
+        return self.client.database(self.database_name);
+    }
+}
+
+#[async_trait]
+impl Storage for MongoStorage {
+    async fn insert_datapoint(&self, data_point: &APYDataPoint) -> Result<()> {
+        let collection_name = format!("data_{}", data_point.data_type.to_string().to_lowercase());
+        let data_points = self.database().collection(collection_name.as_str());
+
+        let insert_data = data_points.insert_one(to_document(&MongoDataPoint::from(data_point))?, None).await?;
+        info!("Data inserted in {}/{}: {}", self.database_name, collection_name, insert_data.inserted_id);
+        return Ok(());
+    }
+
+    async fn insert_datapoints(&self, data_points: &[APYDataPoint]) -> Result<()> {
+        for data_type in [DataType::MINUTE, DataType::HOUR, DataType::DAY, DataType::WEEK].iter() {
+            let documents = data_points.iter()
+                .filter(|data_point| data_point.data_type == *data_type)
+                .map(|data_point| to_document(&MongoDataPoint::from(data_point)))
+                .collect::<std::result::Result<Vec<Document>, _>>()?;
+            if documents.is_empty() {
+                continue;
+            }
+            let collection_name = format!("data_{}", data_type.to_string().to_lowercase());
+            let collection: Collection<Document> = self.database().collection(collection_name.as_str());
+            let insert_data = collection.insert_many(documents, None).await?;
+            info!("{} data points inserted in {}/{}", insert_data.inserted_ids.len(), self.database_name, collection_name);
+        }
+        return Ok(());
+    }
+
+    async fn get_datapoints_between(&self, data_type: DataType, market: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<APYDataPoint>> {
+        use futures::stream::TryStreamExt;
+
+        let collection_name = format!("data_{}", data_type.to_string().to_lowercase());
+        let collection: Collection<MongoDataPoint> = self.database().collection(collection_name.as_str());
+        let filter = doc! {
+            "date": { "$gte": bson::DateTime::from_chrono(from), "$lt": bson::DateTime::from_chrono(to) },
+            "market": market_filter(market)
+        };
+        let find_options = FindOptions::builder().sort(doc! { "date": 1 }).build();
+        let mut cursor = collection.find(filter, find_options).await?;
+
+        let mut result = Vec::<APYDataPoint>::new();
+        while let Some(data_point) = cursor.try_next().await? {
+            result.push(APYDataPoint::from(data_point));
+        }
+        return Ok(result);
+    }
+
+    async fn get_last_date(&self, data_type: DataType, market: &str) -> Result<Option<DateTime<Utc>>> {
+        let collection_name = format!("data_{}", data_type.to_string().to_lowercase());
+        let collection: Collection<MongoDataPoint> = self.database().collection(collection_name.as_str());
+        let find_options = FindOneOptions::builder().sort(doc! { "date": -1 }).build();
+        let data_point = collection.find_one(doc! { "market": market_filter(market) }, find_options).await?;
+        return Ok(data_point.map(|data_point| data_point.date));
+    }
+
+    async fn delete_datapoints(&self, data_type: DataType, market: Option<&str>, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<u64> {
+        let collection_name = format!("data_{}", data_type.to_string().to_lowercase());
+        let collection: Collection<MongoDataPoint> = self.database().collection(collection_name.as_str());
+        let mut filter = doc! { "date": { "$gte": bson::DateTime::from_chrono(from), "$lt": bson::DateTime::from_chrono(to) } };
+        if let Some(market) = market {
+            filter.insert("market", market_filter(market));
+        }
+        let delete_result = collection.delete_many(filter, None).await?;
+        info!("{} data points deleted from {}/{}", delete_result.deleted_count, self.database_name, collection_name);
+        return Ok(delete_result.deleted_count);
+    }
+
+    async fn replace_datapoints(&self, data_point: &APYDataPoint, to: DateTime<Utc>) -> Result<()> {
+        let collection_name = format!("data_{}", data_point.data_type.to_string().to_lowercase());
+        let collection: Collection<Document> = self.database().collection(collection_name.as_str());
+        let date = bson::DateTime::from_chrono(data_point.date);
+
+        // The data point at the date is replaced first, or inserted, then the others are deleted
+        let options = FindOneAndReplaceOptions::builder().upsert(true).return_document(ReturnDocument::After).build();
+        let filter = doc! { "date": date, "market": market_filter(&data_point.market) };
+        let replaced = collection.find_one_and_replace(filter, to_document(&MongoDataPoint::from(data_point))?, options).await?
+            .ok_or(Error::Unavailable(format!("Replaced data point in {}/{}", self.database_name, collection_name)))?;
+        let filter = doc! {
+            "date": { "$gte": date, "$lt": bson::DateTime::from_chrono(to) },
+            "market": market_filter(&data_point.market),
+            "_id": { "$ne": replaced.get("_id").cloned().unwrap_or(Bson::Null) }
+        };
+        let delete_result = collection.delete_many(filter, None).await?;
+        info!("Data replaced in {}/{}: {} and {} more deleted", self.database_name, collection_name, data_point.date, delete_result.deleted_count);
+        return Ok(());
+    }
+
+    async fn get_history(&self, query: &HistoryQuery) -> Result<Vec<AssetHistory>> {
+        use futures::stream::TryStreamExt;
+        query.validate()?;
+
+        let mut pipeline = vec![
+            doc! {
+                "$match": {
+                    "date": { "$gte": bson::DateTime::from_chrono(query.from), "$lte": bson::DateTime::from_chrono(query.to) },
+                    "market": market_filter(&query.market)
+                }
+            },
+            doc! {
+                "$unwind": { "path": "$apys" }
+            },
+        ];
+        if !query.assets.is_empty() {
+            let assets: Vec<&str> = query.assets.iter().map(|asset| asset.0.as_str()).collect();
+            pipeline.push(doc! { "$match": { "apys.asset": { "$in": assets } } });
+        }
+        // The values are pushed rather than averaged, the statistics of every bucket are calculated
+        // in Rust the same way for every backend
+        let values: Vec<String> = query.metrics.iter().map(|metric| format!("$apys.{}", metric.field())).collect();
+        pipeline.push(doc! {
+            "$group": {
+                "_id": { "asset": "$apys.asset", "date": { "$dateFromParts": bucket_date_parts(query.bucket) } },
+                "samples": { "$push": { "date": "$date", "values": values } }
+            }
+        });
+
+        let collection_name = format!("data_{}", query.data_type.to_string().to_lowercase());
+        let data_points: Collection<MongoDataPoint> = self.database().collection(collection_name.as_str());
+        let mut results = data_points.aggregate(pipeline, None).await?;
+        let mut points = Vec::new();
+        while let Some(result) = results.try_next().await? {
+            let key = result.get_document("_id")?;
+            let asset = AssetSymbol(key.get_str("asset")?.to_string());
+            let date = key.get_datetime("date")?.to_chrono();
+            let mut samples = Vec::new();
+            for sample in result.get_array("samples")? {
+                let sample = sample.as_document().ok_or(Error::Decode(format!("Invalid {} sample at {}", asset, date)))?;
+                // Missing fields are pushed as null
+                let values = sample.get_array("values")?.iter().map(|value| match value {
+                    Bson::Double(value) => Some(*value),
+                    Bson::Int32(value) => Some(*value as f64),
+                    Bson::Int64(value) => Some(*value as f64),
+                    _ => None,
+                }).collect();
+                samples.push(Sample { date: sample.get_datetime("date")?.to_chrono(), values });
+            }
+            points.push((asset, query.point(date, samples)));
+        }
+        return Ok(query.collect(points));
+    }
+
+    /// Converts the string dates left by older versions into BSON dates and their APRs into APYs,
+    /// and creates the indexes used by the time range queries
+    async fn migrate(&self) -> Result<()> {
+        use futures::stream::TryStreamExt;
+
+        for data_type in [DataType::MINUTE, DataType::HOUR, DataType::DAY, DataType::WEEK].iter() {
+            let collection_name = format!("data_{}", data_type.to_string().to_lowercase());
+            let collection: Collection<Document> = self.database().collection(collection_name.as_str());
+
+            // Parsed here rather than with $dateFromString, which does not take nanoseconds
+            let filter = doc! { "date": { "$type": "string" } };
+            let find_options = FindOptions::builder().projection(doc! { "date": 1 }).build();
+            let mut cursor = collection.find(filter, find_options).await?;
+            let mut migrated = 0;
+            while let Some(document) = cursor.try_next().await? {
+                let id = document.get("_id").cloned().ok_or(Error::Decode(format!("Data point without _id in {}", collection_name)))?;
+                let date = document.get_str("date").map_err(|e| Error::Decode(e.to_string()))?;
+                let date = parse_legacy_date(date)?;
+                collection.update_one(doc! { "_id": id }, doc! { "$set": { "date": bson::DateTime::from_chrono(date) } }, None).await?;
+                migrated += 1;
+            }
+            info!("{} data points migrated in {}/{}", migrated, self.database_name, collection_name);
+
+            // Data points stored before the interest was compounded hold the APRs in supply and borrow
+            let filter = doc! { "apys": { "$elemMatch": { "supply_apr": { "$exists": false } } } };
+            let find_options = FindOptions::builder().projection(doc! { "apys": 1 }).build();
+            let mut cursor = collection.find(filter, find_options).await?;
+            let mut upgraded = 0;
+            while let Some(document) = cursor.try_next().await? {
+                let id = document.get("_id").cloned().ok_or(Error::Decode(format!("Data point without _id in {}", collection_name)))?;
+                let mut apys: Vec<APY> = bson::from_bson(Bson::Array(document.get_array("apys")?.clone()))?;
+                for apy in apys.iter_mut() {
+                    apy.upgrade_from_apr();
+                }
+                collection.update_one(doc! { "_id": id }, doc! { "$set": { "apys": bson::to_bson(&apys)? } }, None).await?;
+                upgraded += 1;
+            }
+            info!("{} data points converted from APRs to APYs in {}/{}", upgraded, self.database_name, collection_name);
+
+            self.database().run_command(doc! {
+                "createIndexes": collection_name.as_str(),
+                "indexes": [
+                    { "key": { "date": 1 }, "name": "date" },
+                    { "key": { "market": 1, "date": 1 }, "name": "market_date" },
+                    { "key": { "apys.asset": 1, "date": 1 }, "name": "asset_date" },
+                ]
+            }, None).await?;
+            info!("Indexes created in {}/{}", self.database_name, collection_name);
+        }
+        return Ok(());
+    }
+}
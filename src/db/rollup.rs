@@ -0,0 +1,175 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use log::info;
+use std::collections::BTreeMap;
+
+use crate::apy::APY;
+use crate::config::RetentionConfig;
+use crate::db::{APYDataPoint, DataType, SharedStorage};
+use crate::error::Result;
+use crate::history::{AssetHistory, Bucket, HistoryPoint, HistoryQuery, Metric};
+
+/// Every tier is derived from the one below, averaged over its bucket
+const TIERS: [(DataType, DataType, Bucket); 3] = [
+    (DataType::MINUTE, DataType::HOUR, Bucket::HOUR),
+    (DataType::HOUR, DataType::DAY, Bucket::DAY),
+    (DataType::DAY, DataType::WEEK, Bucket::WEEK),
+];
+
+/// Derives the `target` data points of `market` from the tier below, one per bucket completed by
+/// `now`, or the HOUR, DAY and WEEK ones in turn when no tier is given. The latest bucket is
+/// derived again, it may have been rolled up before all its data points came in.
+pub async fn rollup(storage: &SharedStorage, market: &str, target: Option<DataType>, now: DateTime<Utc>) -> Result<()> {
+    return rollup_tiers(storage, market, target, None, now).await;
+}
+
+/// Derives again every bucket from the one `since` falls in, after older data points were added
+pub async fn rollup_since(storage: &SharedStorage, market: &str, since: DateTime<Utc>, now: DateTime<Utc>) -> Result<()> {
+    return rollup_tiers(storage, market, None, Some(since), now).await;
+}
+
+async fn rollup_tiers(storage: &SharedStorage, market: &str, only: Option<DataType>, since: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Result<()> {
+    for (source, target, bucket) in TIERS.iter().filter(|(_, target, _)| only.is_none() || only == Some(*target)) {
+        let to = bucket.start(&now);
+        let from = match since {
+            Some(since) => bucket.start(&since),
+            None => match storage.get_last_date(*target, market).await? {
+                Some(last_date) => bucket.start(&last_date),
+                None => Utc.timestamp(0, 0),
+            },
+        };
+        if from >= to {
+            continue;
+        }
+
+        let data_points = storage.get_datapoints_between(*source, market, from, to).await?;
+        let rolled_up = rollup_datapoints(&data_points, market, *source, *target, *bucket, from, to);
+        for data_point in rolled_up.iter() {
+            // Replaces whatever was stored for the bucket, including points saved by older versions
+            storage.replace_datapoints(data_point, bucket.next(&data_point.date)).await?;
+        }
+        info!("{} {} data points of {} market rolled up into {} {} data points", data_points.len(), source, market, rolled_up.len(), target);
+    }
+    return Ok(());
+}
+
+/// Deletes the data points older than the retention of their tier, in every market
+pub async fn apply_retention(storage: &SharedStorage, retention: &RetentionConfig, now: DateTime<Utc>) -> Result<()> {
+    for data_type in [DataType::MINUTE, DataType::HOUR, DataType::DAY, DataType::WEEK].iter() {
+        if let Some(days) = retention.days(*data_type) {
+            let deleted = storage.delete_datapoints(*data_type, None, Utc.timestamp(0, 0), now - Duration::days(days)).await?;
+            info!("{} {} data points older than {} days pruned", deleted, data_type, days);
+        }
+    }
+    return Ok(());
+}
+
+/// One data point per bucket with the average APYs of `data_points`, which are in chronological
+/// order. The names and weights of the assets, the stats and the slot are the latest ones of the
+/// bucket.
+fn rollup_datapoints(data_points: &[APYDataPoint], market: &str, source: DataType, target: DataType, bucket: Bucket, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<APYDataPoint> {
+    let query = HistoryQuery {
+        market: market.to_string(),
+        assets: Vec::new(),
+        metrics: Metric::all(),
+        from,
+        to,
+        bucket,
+        data_type: source,
+    };
+    let history = query.aggregate(data_points.iter().map(|data_point| (data_point.date, data_point.apys.clone())).collect());
+
+    let mut buckets: BTreeMap<DateTime<Utc>, Vec<&APYDataPoint>> = BTreeMap::new();
+    for data_point in data_points {
+        buckets.entry(bucket.start(&data_point.date)).or_default().push(data_point);
+    }
+
+    let mut result = Vec::new();
+    for (date, data_points) in buckets {
+        let last = data_points[data_points.len() - 1];
+        // Latest APY of every asset in the bucket, an asset may be missing from some data points
+        let mut latest_apys: Vec<&APY> = Vec::new();
+        for data_point in data_points.iter().rev() {
+            for apy in data_point.apys.iter().rev() {
+                if !latest_apys.iter().any(|latest| latest.asset == apy.asset) {
+                    latest_apys.push(apy);
+                }
+            }
+        }
+        latest_apys.reverse();
+
+        let apys = latest_apys.into_iter()
+            .filter_map(|apy| find_point(&history, apy, date).map(|point| averaged_apy(apy, point)))
+            .collect();
+        result.push(APYDataPoint {
+            date,
+            market: market.to_string(),
+            data_type: target,
+            slot: last.slot,
+            apys,
+            stats: last.stats.clone(),
+            backfilled: data_points.iter().any(|data_point| data_point.backfilled),
+        });
+    }
+    return result;
+}
+
+fn find_point<'a>(history: &'a [AssetHistory], apy: &APY, date: DateTime<Utc>) -> Option<&'a HistoryPoint> {
+    return history.iter()
+        .find(|asset_history| asset_history.asset == apy.asset)
+        .and_then(|asset_history| asset_history.points.iter().find(|point| point.date == date));
+}
+
+/// `apy` with its values replaced by the averages of the bucket
+fn averaged_apy(apy: &APY, point: &HistoryPoint) -> APY {
+    let mean = |metric: Metric| point.values.get(&metric).cloned().flatten();
+    return APY {
+        price: mean(Metric::PRICE).unwrap_or(apy.price),
+        supply: mean(Metric::SUPPLY).unwrap_or(apy.supply),
+        borrow: mean(Metric::BORROW).unwrap_or(apy.borrow),
+        supply_rewards: mean(Metric::SUPPLY_REWARDS).unwrap_or(apy.supply_rewards),
+        borrow_rewards: mean(Metric::BORROW_REWARDS).unwrap_or(apy.borrow_rewards),
+        mnde_supply_rewards: mean(Metric::MNDE_SUPPLY_REWARDS).or(apy.mnde_supply_rewards),
+        utilization: mean(Metric::UTILIZATION).or(apy.utilization),
+        supply_apr: mean(Metric::SUPPLY_APR).or(apy.supply_apr),
+        borrow_apr: mean(Metric::BORROW_APR).or(apy.borrow_apr),
+        supply_gross: mean(Metric::SUPPLY_GROSS).or(apy.supply_gross),
+        ..apy.clone()
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{data_point, TempStorage};
+    use crate::db::Storage;
+
+    #[test]
+    fn rollup_replaces_the_buckets_of_its_tier() {
+        let temp = TempStorage::new("rollup");
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let hour = |hour: u32, minute: u32| Utc.ymd(2021, 11, 3).and_hms(hour, minute, 0);
+
+        runtime.block_on(temp.storage.insert_datapoints(&[
+            data_point(hour(10, 0), DataType::MINUTE, 0.05),
+            data_point(hour(10, 30), DataType::MINUTE, 0.06),
+            data_point(hour(11, 0), DataType::MINUTE, 0.07),
+            // Stored by an older version in the middle of the bucket
+            data_point(hour(10, 15), DataType::HOUR, 0.5),
+        ])).unwrap();
+
+        // Running it again changes nothing
+        for _ in 0..2 {
+            runtime.block_on(rollup(&temp.storage, "main", Some(DataType::HOUR), hour(12, 0))).unwrap();
+            let hours = runtime.block_on(temp.storage.get_datapoints_between(DataType::HOUR, "main", hour(0, 0), hour(23, 0))).unwrap();
+            let dates: Vec<DateTime<Utc>> = hours.iter().map(|data_point| data_point.date).collect();
+            assert_eq!(dates, vec![hour(10, 0), hour(11, 0)]);
+            assert_eq!(hours[0].apys.iter().map(|apy| apy.asset.0.as_str()).collect::<Vec<&str>>(), vec!["SOL", "USDC"]);
+            assert!((hours[0].apys[0].supply - 0.055).abs() < 1e-9);
+            assert!((hours[1].apys[0].supply - 0.07).abs() < 1e-9);
+        }
+
+        // The other tiers are left to their own jobs
+        let days = runtime.block_on(temp.storage.get_datapoints_between(DataType::DAY, "main", Utc.timestamp(0, 0), hour(12, 0))).unwrap();
+        assert!(days.is_empty());
+    }
+}
@@ -0,0 +1,239 @@
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::info;
+use rusqlite::{params, Connection};
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::apy::APY;
+use crate::db::{APYDataPoint, DataType, Storage};
+use crate::error::{Error, Result};
+use crate::history::{AssetHistory, HistoryQuery};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS data_points (
+        id INTEGER PRIMARY KEY,
+        data_type TEXT NOT NULL,
+        market TEXT NOT NULL,
+        date TEXT NOT NULL,
+        slot INTEGER,
+        apys TEXT NOT NULL,
+        stats TEXT,
+        backfilled INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX IF NOT EXISTS data_points_type_market_date ON data_points (data_type, market, date);
+";
+
+/// Keeps the data points in a local SQLite file, in a single table with the APYs and stats stored
+/// as JSON. Dates are RFC 3339 strings in UTC with a fixed precision, so they sort chronologically.
+pub struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+}
+
+/// Columns of a data point row: date, market, slot, apys, stats and backfilled
+type Row = (String, String, Option<i64>, String, Option<String>, bool);
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> Result<Self> {
+        fs::create_dir_all(path.parent().unwrap_or(Path::new("")))?;
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+        // Files created before backfills were tracked lack the column
+        if connection.prepare("SELECT backfilled FROM data_points LIMIT 0").is_err() {
+            connection.execute_batch("ALTER TABLE data_points ADD COLUMN backfilled INTEGER NOT NULL DEFAULT 0")?;
+        }
+        info!("SQLite storage opened at {}", path.display());
+        return Ok(Self { connection: Arc::new(Mutex::new(connection)) });
+    }
+
+    /// rusqlite is blocking, so queries run on the blocking thread pool of the runtime
+    async fn with_connection<T, F>(&self, f: F) -> Result<T> where T: Send + 'static, F: FnOnce(&Connection) -> Result<T> + Send + 'static {
+        let connection = self.connection.clone();
+        return tokio::task::spawn_blocking(move || f(&connection.lock().unwrap())).await.map_err(std::io::Error::from)?;
+    }
+}
+
+fn format_date(date: &DateTime<Utc>) -> String {
+    return date.to_rfc3339_opts(SecondsFormat::Millis, true);
+}
+
+fn parse_date(date: &str) -> Result<DateTime<Utc>> {
+    return DateTime::parse_from_rfc3339(date)
+        .map(|date| date.with_timezone(&Utc))
+        .map_err(|e| Error::Decode(format!("Invalid date '{}': {}", date, e)));
+}
+
+fn read_datapoint(data_type: DataType, row: Row) -> Result<APYDataPoint> {
+    let (date, market, slot, apys, stats, backfilled) = row;
+    return Ok(APYDataPoint {
+        date: parse_date(&date)?,
+        market,
+        data_type,
+        slot: slot.map(|slot| slot as u64),
+        apys: serde_json::from_str(&apys)?,
+        stats: stats.map(|stats| serde_json::from_str(&stats)).transpose()?,
+        backfilled,
+    });
+}
+
+fn insert_row(connection: &Connection, data_point: &APYDataPoint) -> Result<()> {
+    connection.execute(
+        "INSERT INTO data_points (data_type, market, date, slot, apys, stats, backfilled) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            data_point.data_type.to_string(),
+            data_point.market,
+            format_date(&data_point.date),
+            data_point.slot.map(|slot| slot as i64),
+            serde_json::to_string(&data_point.apys)?,
+            data_point.stats.as_ref().map(serde_json::to_string).transpose()?,
+            data_point.backfilled,
+        ],
+    )?;
+    return Ok(());
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn insert_datapoint(&self, data_point: &APYDataPoint) -> Result<()> {
+        let data_point = data_point.clone();
+        return self.with_connection(move |connection| {
+            insert_row(connection, &data_point)?;
+            info!("Data inserted in data_points: {} {}", data_point.data_type, data_point.date);
+            return Ok(());
+        }).await;
+    }
+
+    async fn insert_datapoints(&self, data_points: &[APYDataPoint]) -> Result<()> {
+        let data_points = data_points.to_vec();
+        return self.with_connection(move |connection| {
+            // A single transaction is much faster than one per row, and leaves nothing behind on failure
+            let transaction = connection.unchecked_transaction()?;
+            for data_point in data_points.iter() {
+                insert_row(&transaction, data_point)?;
+            }
+            transaction.commit()?;
+            info!("{} data points inserted in data_points", data_points.len());
+            return Ok(());
+        }).await;
+    }
+
+    async fn get_datapoints_between(&self, data_type: DataType, market: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<APYDataPoint>> {
+        let (market, from, to) = (market.to_string(), format_date(&from), format_date(&to));
+        return self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT date, market, slot, apys, stats, backfilled FROM data_points WHERE data_type = ?1 AND market = ?2 AND date >= ?3 AND date < ?4 ORDER BY date ASC"
+            )?;
+            let rows = statement.query_map::<Row, _, _>(params![data_type.to_string(), market, from, to], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?))
+            })?;
+            let mut result = Vec::<APYDataPoint>::new();
+            for row in rows {
+                result.push(read_datapoint(data_type, row?)?);
+            }
+            return Ok(result);
+        }).await;
+    }
+
+    async fn get_last_date(&self, data_type: DataType, market: &str) -> Result<Option<DateTime<Utc>>> {
+        let market = market.to_string();
+        let date: Option<String> = self.with_connection(move |connection| {
+            let date = connection.query_row(
+                "SELECT MAX(date) FROM data_points WHERE data_type = ?1 AND market = ?2",
+                params![data_type.to_string(), market],
+                |row| row.get(0),
+            )?;
+            return Ok(date);
+        }).await?;
+        return date.map(|date| parse_date(&date)).transpose();
+    }
+
+    async fn delete_datapoints(&self, data_type: DataType, market: Option<&str>, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<u64> {
+        let (market, from, to) = (market.map(str::to_string), format_date(&from), format_date(&to));
+        return self.with_connection(move |connection| {
+            // A missing market matches every market
+            let deleted = connection.execute(
+                "DELETE FROM data_points WHERE data_type = ?1 AND (?2 IS NULL OR market = ?2) AND date >= ?3 AND date < ?4",
+                params![data_type.to_string(), market, from, to],
+            )?;
+            info!("{} data points deleted from data_points: {}", deleted, data_type);
+            return Ok(deleted as u64);
+        }).await;
+    }
+
+    async fn replace_datapoints(&self, data_point: &APYDataPoint, to: DateTime<Utc>) -> Result<()> {
+        let (data_point, to) = (data_point.clone(), format_date(&to));
+        return self.with_connection(move |connection| {
+            let transaction = connection.unchecked_transaction()?;
+            transaction.execute(
+                "DELETE FROM data_points WHERE data_type = ?1 AND market = ?2 AND date >= ?3 AND date < ?4",
+                params![data_point.data_type.to_string(), data_point.market, format_date(&data_point.date), to],
+            )?;
+            insert_row(&transaction, &data_point)?;
+            transaction.commit()?;
+            info!("Data replaced in data_points: {} {}", data_point.data_type, data_point.date);
+            return Ok(());
+        }).await;
+    }
+
+    async fn get_history(&self, query: &HistoryQuery) -> Result<Vec<AssetHistory>> {
+        query.validate()?;
+        let (data_type, market, from, to) = (query.data_type.to_string(), query.market.clone(), format_date(&query.from), format_date(&query.to));
+        let rows: Vec<(String, String)> = self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT date, apys FROM data_points WHERE data_type = ?1 AND market = ?2 AND date >= ?3 AND date <= ?4"
+            )?;
+            let rows = statement.query_map::<(String, String), _, _>(params![data_type, market, from, to], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
+            let mut result = Vec::new();
+            for row in rows {
+                result.push(row?);
+            }
+            return Ok(result);
+        }).await?;
+
+        let mut data_points = Vec::new();
+        for (date, apys) in rows {
+            let apys: Vec<APY> = serde_json::from_str(&apys)?;
+            data_points.push((parse_date(&date)?, apys));
+        }
+        return Ok(query.aggregate(data_points));
+    }
+
+    /// Converts the APRs of the data points stored before the interest was compounded into APYs.
+    /// The schema and its indexes are created when the file is opened.
+    async fn migrate(&self) -> Result<()> {
+        let upgraded: u64 = self.with_connection(|connection| {
+            let transaction = connection.unchecked_transaction()?;
+            let mut rows = Vec::new();
+            {
+                let mut statement = transaction.prepare("SELECT id, apys FROM data_points")?;
+                let result = statement.query_map::<(i64, String), _, _>(params![], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?;
+                for row in result {
+                    rows.push(row?);
+                }
+            }
+            let mut upgraded = 0;
+            for (id, apys) in rows {
+                let mut apys: Vec<APY> = serde_json::from_str(&apys)?;
+                let mut changed = false;
+                for apy in apys.iter_mut() {
+                    changed |= apy.upgrade_from_apr();
+                }
+                if changed {
+                    transaction.execute("UPDATE data_points SET apys = ?1 WHERE id = ?2", params![serde_json::to_string(&apys)?, id])?;
+                    upgraded += 1;
+                }
+            }
+            transaction.commit()?;
+            return Ok(upgraded);
+        }).await?;
+        info!("{} data points converted from APRs to APYs in SQLite storage", upgraded);
+        return Ok(());
+    }
+}
@@ -0,0 +1,182 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use solana_sdk::program_error::ProgramError;
+use std::fmt;
+
+use crate::utils::AssetSymbol;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A Solana RPC node answered a call with an error
+    Rpc(String),
+    /// An HTTP request to an external API failed
+    Http(reqwest::Error),
+    /// Data was received but could not be decoded or used in a calculation
+    Decode(String),
+    /// The configuration is missing or invalid
+    Config(String),
+    /// The database could not be reached or rejected the operation
+    Database(String),
+    /// A local file could not be read or written
+    Io(std::io::Error),
+    /// The requested market, asset or resource does not exist
+    NotFound(String),
+    /// The resource exists but its data could not be fetched lately
+    Unavailable(String),
+    /// The parameters of a request are missing or invalid
+    InvalidRequest(String),
+}
+
+impl Error {
+    pub fn kind(&self) -> &'static str {
+        return match self {
+            Error::Rpc(_) => "rpc",
+            Error::Http(_) => "http",
+            Error::Decode(_) => "decode",
+            Error::Config(_) => "config",
+            Error::Database(_) => "database",
+            Error::Io(_) => "io",
+            Error::NotFound(_) => "not_found",
+            Error::Unavailable(_) => "unavailable",
+            Error::InvalidRequest(_) => "invalid_request",
+        };
+    }
+
+    /// Exit code of the CLI when a command fails with this error: 2 when it cannot run as invoked,
+    /// 3 when a service it relies on failed and a later run may succeed, 1 otherwise
+    pub fn exit_code(&self) -> i32 {
+        return match self {
+            Error::Config(_) | Error::InvalidRequest(_) | Error::NotFound(_) => 2,
+            Error::Rpc(_) | Error::Http(_) | Error::Database(_) | Error::Unavailable(_) => 3,
+            Error::Decode(_) | Error::Io(_) => 1,
+        };
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Rpc(e) => write!(f, "RPC error: {}", e),
+            Error::Http(e) => write!(f, "HTTP error: {}", e),
+            Error::Decode(e) => write!(f, "Decode error: {}", e),
+            Error::Config(e) => write!(f, "Config error: {}", e),
+            Error::Database(e) => write!(f, "Database error: {}", e),
+            Error::Io(e) => write!(f, "IO error: {}", e),
+            Error::NotFound(e) => write!(f, "{} not found", e),
+            Error::Unavailable(e) => write!(f, "{} is unavailable", e),
+            Error::InvalidRequest(e) => write!(f, "Invalid request: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+impl From<ProgramError> for Error {
+    fn from(e: ProgramError) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+impl From<config::ConfigError> for Error {
+    fn from(e: config::ConfigError) -> Self {
+        Error::Config(e.to_string())
+    }
+}
+
+impl From<mongodb::error::Error> for Error {
+    fn from(e: mongodb::error::Error) -> Self {
+        Error::Database(e.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Database(e.to_string())
+    }
+}
+
+impl From<mongodb::bson::ser::Error> for Error {
+    fn from(e: mongodb::bson::ser::Error) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+impl From<mongodb::bson::de::Error> for Error {
+    fn from(e: mongodb::bson::de::Error) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+impl From<mongodb::bson::document::ValueAccessError> for Error {
+    fn from(e: mongodb::bson::document::ValueAccessError) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+impl From<parquet::errors::ParquetError> for Error {
+    fn from(e: parquet::errors::ParquetError) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ErrorBody {
+    pub error: String,
+    pub message: String,
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            Error::Rpc(_) | Error::Http(_) | Error::Decode(_) => StatusCode::BAD_GATEWAY,
+            Error::Database(_) | Error::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Config(_) | Error::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let body = ErrorBody { error: self.kind().to_string(), message: self.to_string() };
+        HttpResponse::build(self.status_code()).json(&body)
+    }
+}
+
+/// Failure of a single asset, reported next to the assets that succeeded
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AssetError {
+    pub asset: AssetSymbol,
+    pub error: String,
+}
+
+impl AssetError {
+    pub fn new(asset: AssetSymbol, error: &Error) -> Self {
+        return Self { asset, error: error.to_string() };
+    }
+}
@@ -0,0 +1,393 @@
+use chrono::{DateTime, Duration, Utc};
+use parquet::{
+    column::writer::ColumnWriter,
+    data_type::ByteArray,
+    file::{
+        properties::WriterProperties,
+        writer::{FileWriter, InMemoryWriteableCursor, SerializedFileWriter},
+    },
+    schema::parser::parse_message_type,
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr, sync::Arc};
+
+use crate::apy::APY;
+use crate::db::{APYDataPoint, DataType, SharedStorage};
+use crate::error::{Error, Result};
+use crate::history::{parse_param_date, split_list};
+use crate::utils::{AssetSymbol, Market};
+
+const DEFAULT_DAYS_BACK: i64 = 7;
+
+const PARQUET_SCHEMA: &str = "
+    message apy_data_point {
+        REQUIRED INT64 date (TIMESTAMP_MILLIS);
+        REQUIRED BYTE_ARRAY market (UTF8);
+        REQUIRED BYTE_ARRAY data_type (UTF8);
+        OPTIONAL INT64 slot;
+        REQUIRED BYTE_ARRAY asset (UTF8);
+        REQUIRED BYTE_ARRAY name (UTF8);
+        REQUIRED DOUBLE price;
+        REQUIRED DOUBLE supply;
+        REQUIRED DOUBLE borrow;
+        REQUIRED DOUBLE supply_rewards;
+        REQUIRED DOUBLE borrow_rewards;
+        OPTIONAL DOUBLE mnde_supply_rewards;
+        OPTIONAL DOUBLE utilization;
+        OPTIONAL DOUBLE supply_apr;
+        OPTIONAL DOUBLE borrow_apr;
+        OPTIONAL DOUBLE supply_gross;
+        REQUIRED BYTE_ARRAY weight_supply (UTF8);
+        REQUIRED BYTE_ARRAY weight_borrow (UTF8);
+        REQUIRED BOOLEAN backfilled;
+    }
+";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExportFormat {
+    CSV,
+    NDJSON,
+    PARQUET,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        return match self {
+            ExportFormat::CSV => "text/csv",
+            ExportFormat::NDJSON => "application/x-ndjson",
+            ExportFormat::PARQUET => "application/vnd.apache.parquet",
+        };
+    }
+
+    pub fn extension(&self) -> &'static str {
+        return match self {
+            ExportFormat::CSV => "csv",
+            ExportFormat::NDJSON => "ndjson",
+            ExportFormat::PARQUET => "parquet",
+        };
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "CSV" | "csv" => Ok(ExportFormat::CSV),
+            "NDJSON" | "ndjson" | "JSONL" | "jsonl" => Ok(ExportFormat::NDJSON),
+            "PARQUET" | "parquet" => Ok(ExportFormat::PARQUET),
+            _ => Err(format!("'{}' is not a valid value for ExportFormat", s)),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Flat form of a data point, one per asset. The market stats are not exported.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportRow {
+    pub date: DateTime<Utc>,
+    pub market: String,
+    pub data_type: DataType,
+    pub slot: Option<u64>,
+    pub asset: AssetSymbol,
+    pub name: String,
+    pub price: f64,
+    pub supply: f64,
+    pub borrow: f64,
+    pub supply_rewards: f64,
+    pub borrow_rewards: f64,
+    pub mnde_supply_rewards: Option<f64>,
+    pub utilization: Option<f64>,
+    #[serde(default)]
+    pub supply_apr: Option<f64>,
+    #[serde(default)]
+    pub borrow_apr: Option<f64>,
+    #[serde(default)]
+    pub supply_gross: Option<f64>,
+    pub weight_supply: String,
+    pub weight_borrow: String,
+    #[serde(default)]
+    pub backfilled: bool,
+}
+
+impl ExportRow {
+    pub fn new(data_point: &APYDataPoint, apy: &APY) -> Self {
+        return Self {
+            date: data_point.date,
+            market: data_point.market.clone(),
+            data_type: data_point.data_type,
+            slot: data_point.slot,
+            asset: apy.asset.clone(),
+            name: apy.name.clone(),
+            price: apy.price,
+            supply: apy.supply,
+            borrow: apy.borrow,
+            supply_rewards: apy.supply_rewards,
+            borrow_rewards: apy.borrow_rewards,
+            mnde_supply_rewards: apy.mnde_supply_rewards,
+            utilization: apy.utilization,
+            supply_apr: apy.supply_apr,
+            borrow_apr: apy.borrow_apr,
+            supply_gross: apy.supply_gross,
+            weight_supply: apy.weight_supply.clone(),
+            weight_borrow: apy.weight_borrow.clone(),
+            backfilled: data_point.backfilled,
+        };
+    }
+}
+
+/// Query string of the export route, every parameter is optional
+#[derive(Deserialize, Debug, Default)]
+pub struct ExportParams {
+    /// Comma separated asset symbols
+    pub asset: Option<String>,
+    /// RFC 3339 date or YYYY-MM-DD
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub format: Option<String>,
+    pub data: Option<String>,
+}
+
+/// Data points of `data_type` in `market` between `from` and `to`, as rows of `assets`
+#[derive(Debug, Clone)]
+pub struct ExportQuery {
+    pub market: String,
+    /// Every asset when empty
+    pub assets: Vec<AssetSymbol>,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub data_type: DataType,
+    pub format: ExportFormat,
+}
+
+impl ExportQuery {
+    /// CSV rows of the hourly data points of every asset over the last 7 days, unless stated
+    /// otherwise
+    pub fn from_params(params: &ExportParams, market: &Market) -> Result<Self> {
+        let mut assets = Vec::new();
+        for symbol in split_list(&params.asset) {
+            let asset = market.assets().into_iter()
+                .find(|a| a.0.eq_ignore_ascii_case(symbol))
+                .ok_or(Error::NotFound(format!("Asset {} in {} market", symbol, market.name)))?;
+            assets.push(asset);
+        }
+        let to = match &params.to {
+            Some(to) => parse_param_date(to, true)?,
+            None => Utc::now(),
+        };
+        let from = match &params.from {
+            Some(from) => parse_param_date(from, false)?,
+            None => to - Duration::days(DEFAULT_DAYS_BACK),
+        };
+        if from > to {
+            return Err(Error::InvalidRequest(format!("from {} is after to {}", from, to)));
+        }
+        let data_type = match &params.data {
+            Some(data_type) => DataType::from_str(data_type).map_err(Error::InvalidRequest)?,
+            None => DataType::HOUR,
+        };
+        let format = match &params.format {
+            Some(format) => ExportFormat::from_str(format).map_err(Error::InvalidRequest)?,
+            None => ExportFormat::CSV,
+        };
+        return Ok(Self { market: market.name.clone(), assets, from, to, data_type, format });
+    }
+
+    fn rows(&self, data_points: &[APYDataPoint]) -> Vec<ExportRow> {
+        return data_points.iter()
+            .flat_map(|data_point| data_point.apys.iter()
+                .filter(|apy| self.assets.is_empty() || self.assets.contains(&apy.asset))
+                .map(move |apy| ExportRow::new(data_point, apy)))
+            .collect();
+    }
+}
+
+/// Reads the data points of `query` a day at a time and hands every encoded chunk to `write`, so
+/// the whole range is never held in memory. Parquet files end with their metadata, they are only
+/// written once complete. Returns the number of rows exported.
+pub async fn export<F: FnMut(Vec<u8>) -> Result<()>>(storage: &SharedStorage, query: &ExportQuery, mut write: F) -> Result<usize> {
+    let mut parquet_rows = Vec::new();
+    let mut count = 0;
+    let mut header_written = false;
+    let mut from = query.from;
+    while from <= query.to {
+        let to = std::cmp::min(from + Duration::days(1), query.to + Duration::milliseconds(1));
+        let rows = query.rows(&storage.get_datapoints_between(query.data_type, &query.market, from, to).await?);
+        match query.format {
+            ExportFormat::CSV => {
+                write(encode_csv(&rows, !header_written)?)?;
+                header_written = true;
+            },
+            ExportFormat::NDJSON => write(encode_ndjson(&rows)?)?,
+            ExportFormat::PARQUET => parquet_rows.extend(rows.iter().cloned()),
+        }
+        count += rows.len();
+        from = to;
+    }
+    if query.format == ExportFormat::PARQUET {
+        write(encode_parquet(&parquet_rows)?)?;
+    }
+    return Ok(count);
+}
+
+/// The header is written with the first rows, or on its own when there are none
+fn encode_csv(rows: &[ExportRow], header: bool) -> Result<Vec<u8>> {
+    let mut writer = csv::WriterBuilder::new().has_headers(header).from_writer(Vec::new());
+    if rows.is_empty() && header {
+        writer.write_record(["date", "market", "data_type", "slot", "asset", "name", "price", "supply", "borrow", "supply_rewards",
+            "borrow_rewards", "mnde_supply_rewards", "utilization", "supply_apr", "borrow_apr", "supply_gross", "weight_supply", "weight_borrow", "backfilled"])?;
+    }
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    return writer.into_inner().map_err(|e| Error::Decode(e.to_string()));
+}
+
+fn encode_ndjson(rows: &[ExportRow]) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    for row in rows {
+        serde_json::to_writer(&mut body, row)?;
+        body.push(b'\n');
+    }
+    return Ok(body);
+}
+
+/// Values of a Parquet column, only the optional ones hold nulls
+enum Column<'a> {
+    Int64(Vec<Option<i64>>, bool),
+    Double(Vec<Option<f64>>, bool),
+    Text(Vec<&'a str>),
+    Boolean(Vec<bool>),
+}
+
+fn encode_parquet(rows: &[ExportRow]) -> Result<Vec<u8>> {
+    let data_types: Vec<String> = rows.iter().map(|row| row.data_type.to_string()).collect();
+    // In the order of the schema
+    let mut columns = vec![
+        Column::Int64(rows.iter().map(|row| Some(row.date.timestamp_millis())).collect(), false),
+        Column::Text(rows.iter().map(|row| row.market.as_str()).collect()),
+        Column::Text(data_types.iter().map(String::as_str).collect()),
+        Column::Int64(rows.iter().map(|row| row.slot.map(|slot| slot as i64)).collect(), true),
+        Column::Text(rows.iter().map(|row| row.asset.0.as_str()).collect()),
+        Column::Text(rows.iter().map(|row| row.name.as_str()).collect()),
+        Column::Double(rows.iter().map(|row| Some(row.price)).collect(), false),
+        Column::Double(rows.iter().map(|row| Some(row.supply)).collect(), false),
+        Column::Double(rows.iter().map(|row| Some(row.borrow)).collect(), false),
+        Column::Double(rows.iter().map(|row| Some(row.supply_rewards)).collect(), false),
+        Column::Double(rows.iter().map(|row| Some(row.borrow_rewards)).collect(), false),
+        Column::Double(rows.iter().map(|row| row.mnde_supply_rewards).collect(), true),
+        Column::Double(rows.iter().map(|row| row.utilization).collect(), true),
+        Column::Double(rows.iter().map(|row| row.supply_apr).collect(), true),
+        Column::Double(rows.iter().map(|row| row.borrow_apr).collect(), true),
+        Column::Double(rows.iter().map(|row| row.supply_gross).collect(), true),
+        Column::Text(rows.iter().map(|row| row.weight_supply.as_str()).collect()),
+        Column::Text(rows.iter().map(|row| row.weight_borrow.as_str()).collect()),
+        Column::Boolean(rows.iter().map(|row| row.backfilled).collect()),
+    ].into_iter();
+
+    let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+    let properties = Arc::new(WriterProperties::builder().build());
+    let cursor = InMemoryWriteableCursor::default();
+    let mut file_writer = SerializedFileWriter::new(cursor.clone(), schema, properties)?;
+    let mut row_group_writer = file_writer.next_row_group()?;
+    while let Some(mut column_writer) = row_group_writer.next_column()? {
+        let column = columns.next().ok_or(Error::Decode("Parquet schema has more columns than the rows".to_string()))?;
+        write_column(&mut column_writer, column)?;
+        row_group_writer.close_column(column_writer)?;
+    }
+    file_writer.close_row_group(row_group_writer)?;
+    file_writer.close()?;
+    return Ok(cursor.data());
+}
+
+fn write_column(column_writer: &mut ColumnWriter, column: Column) -> Result<()> {
+    match (column_writer, column) {
+        (ColumnWriter::Int64ColumnWriter(writer), Column::Int64(values, optional)) => {
+            let (values, levels) = definition_levels(values, optional);
+            writer.write_batch(&values, levels.as_deref(), None)?;
+        },
+        (ColumnWriter::DoubleColumnWriter(writer), Column::Double(values, optional)) => {
+            let (values, levels) = definition_levels(values, optional);
+            writer.write_batch(&values, levels.as_deref(), None)?;
+        },
+        (ColumnWriter::ByteArrayColumnWriter(writer), Column::Text(values)) => {
+            let values: Vec<ByteArray> = values.into_iter().map(ByteArray::from).collect();
+            writer.write_batch(&values, None, None)?;
+        },
+        (ColumnWriter::BoolColumnWriter(writer), Column::Boolean(values)) => {
+            writer.write_batch(&values, None, None)?;
+        },
+        _ => return Err(Error::Decode("Parquet column does not match the schema".to_string())),
+    }
+    return Ok(());
+}
+
+/// Non null values of an optional column along with the definition level of every row
+fn definition_levels<T>(values: Vec<Option<T>>, optional: bool) -> (Vec<T>, Option<Vec<i16>>) {
+    if !optional {
+        return (values.into_iter().flatten().collect(), None);
+    }
+    let levels = values.iter().map(|value| if value.is_some() { 1 } else { 0 }).collect();
+    return (values.into_iter().flatten().collect(), Some(levels));
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::db::testing::{data_point, TempStorage};
+    use crate::db::Storage;
+    use crate::import;
+    use crate::utils::{Cluster, ProgramConfig};
+
+    #[test]
+    fn csv_round_trip_from_empty_day() {
+        let program_config = ProgramConfig::from_json(Cluster::MAINNET.embedded_config().unwrap()).unwrap();
+        let market = program_config.market("main").unwrap();
+        let exported = TempStorage::new("export-round-trip-source");
+        let imported = TempStorage::new("export-round-trip-target");
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        // Nothing is stored on the first two days of the range
+        let data_points = vec![
+            data_point(Utc.ymd(2021, 11, 3).and_hms(10, 0, 0), DataType::HOUR, 0.05),
+            data_point(Utc.ymd(2021, 11, 3).and_hms(11, 0, 0), DataType::HOUR, 0.06),
+            data_point(Utc.ymd(2021, 11, 4).and_hms(10, 0, 0), DataType::HOUR, 0.07),
+        ];
+        runtime.block_on(exported.storage.insert_datapoints(&data_points)).unwrap();
+
+        let params = ExportParams { asset: None, from: Some("2021-11-01".to_string()), to: Some("2021-11-04".to_string()), format: Some("csv".to_string()), data: None };
+        let query = ExportQuery::from_params(&params, market).unwrap();
+        let mut body = Vec::new();
+        let count = runtime.block_on(export(&exported.storage, &query, |chunk| {
+            body.extend(chunk);
+            return Ok(());
+        })).unwrap();
+        assert_eq!(count, 6);
+
+        let body = String::from_utf8(body).unwrap();
+        assert_eq!(body.lines().filter(|line| line.starts_with("date,")).count(), 1);
+        assert_eq!(body.lines().count(), 7);
+
+        let path = std::env::temp_dir().join(format!("solend-apy-bot-export-round-trip-{}.csv", std::process::id()));
+        std::fs::write(&path, body).unwrap();
+        let rows = import::read_rows(&path, ExportFormat::CSV, &program_config);
+        std::fs::remove_file(&path).unwrap();
+        let rows = rows.unwrap();
+        assert_eq!(rows.len(), 6);
+
+        let report = runtime.block_on(import::import(&imported.storage, rows)).unwrap();
+        assert_eq!(report.inserted, 6);
+        assert_eq!(report.duplicates, 0);
+        let stored = runtime.block_on(imported.storage.get_datapoints_between(DataType::HOUR, "main", query.from, query.to)).unwrap();
+        assert_eq!(stored.len(), 3);
+        assert_eq!(stored[2].date, data_points[2].date);
+        assert_eq!(stored[2].apys[0].supply, 0.07);
+    }
+}
@@ -0,0 +1,464 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    str::FromStr,
+};
+
+use crate::apy::APY;
+use crate::db::DataType;
+use crate::error::{Error, Result};
+use crate::utils::{AssetSymbol, Market};
+
+const DEFAULT_DAYS_BACK: i64 = 7;
+
+/// Size of the time buckets the data points are averaged over
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Bucket {
+    HOUR,
+    DAY,
+    WEEK,
+    MONTH,
+}
+
+impl Bucket {
+    /// Start of the bucket `date` falls in, in UTC. Weeks start on Monday.
+    pub fn start(&self, date: &DateTime<Utc>) -> DateTime<Utc> {
+        return match self {
+            Bucket::HOUR => Utc.ymd(date.year(), date.month(), date.day()).and_hms(date.hour(), 0, 0),
+            Bucket::DAY => Utc.ymd(date.year(), date.month(), date.day()).and_hms(0, 0, 0),
+            Bucket::WEEK => (date.date() - Duration::days(date.weekday().num_days_from_monday() as i64)).and_hms(0, 0, 0),
+            Bucket::MONTH => Utc.ymd(date.year(), date.month(), 1).and_hms(0, 0, 0),
+        };
+    }
+
+    /// Start of the bucket following the one starting at `start`
+    pub fn next(&self, start: &DateTime<Utc>) -> DateTime<Utc> {
+        return match self {
+            Bucket::HOUR => *start + Duration::hours(1),
+            Bucket::DAY => *start + Duration::days(1),
+            Bucket::WEEK => *start + Duration::weeks(1),
+            Bucket::MONTH if start.month() == 12 => Utc.ymd(start.year() + 1, 1, 1).and_hms(0, 0, 0),
+            Bucket::MONTH => Utc.ymd(start.year(), start.month() + 1, 1).and_hms(0, 0, 0),
+        };
+    }
+}
+
+impl FromStr for Bucket {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "HOUR" | "hour" => Ok(Bucket::HOUR),
+            "DAY" | "day" => Ok(Bucket::DAY),
+            "WEEK" | "week" => Ok(Bucket::WEEK),
+            "MONTH" | "month" => Ok(Bucket::MONTH),
+            _ => Err(format!("'{}' is not a valid value for Bucket", s)),
+        }
+    }
+}
+
+impl fmt::Display for Bucket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Value of an APY that can be aggregated over time, named after its field
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    SUPPLY,
+    BORROW,
+    PRICE,
+    SUPPLY_REWARDS,
+    BORROW_REWARDS,
+    MNDE_SUPPLY_REWARDS,
+    UTILIZATION,
+    SUPPLY_APR,
+    BORROW_APR,
+    SUPPLY_GROSS,
+}
+
+impl Metric {
+    pub fn all() -> Vec<Metric> {
+        return vec![
+            Metric::SUPPLY,
+            Metric::BORROW,
+            Metric::PRICE,
+            Metric::SUPPLY_REWARDS,
+            Metric::BORROW_REWARDS,
+            Metric::MNDE_SUPPLY_REWARDS,
+            Metric::UTILIZATION,
+            Metric::SUPPLY_APR,
+            Metric::BORROW_APR,
+            Metric::SUPPLY_GROSS,
+        ];
+    }
+
+    /// Name of the APY field holding the metric
+    pub fn field(&self) -> &'static str {
+        return match self {
+            Metric::SUPPLY => "supply",
+            Metric::BORROW => "borrow",
+            Metric::PRICE => "price",
+            Metric::SUPPLY_REWARDS => "supply_rewards",
+            Metric::BORROW_REWARDS => "borrow_rewards",
+            Metric::MNDE_SUPPLY_REWARDS => "mnde_supply_rewards",
+            Metric::UTILIZATION => "utilization",
+            Metric::SUPPLY_APR => "supply_apr",
+            Metric::BORROW_APR => "borrow_apr",
+            Metric::SUPPLY_GROSS => "supply_gross",
+        };
+    }
+
+    pub fn value(&self, apy: &APY) -> Option<f64> {
+        return match self {
+            Metric::SUPPLY => Some(apy.supply),
+            Metric::BORROW => Some(apy.borrow),
+            Metric::PRICE => Some(apy.price),
+            Metric::SUPPLY_REWARDS => Some(apy.supply_rewards),
+            Metric::BORROW_REWARDS => Some(apy.borrow_rewards),
+            Metric::MNDE_SUPPLY_REWARDS => apy.mnde_supply_rewards,
+            Metric::UTILIZATION => apy.utilization,
+            Metric::SUPPLY_APR => apy.supply_apr,
+            Metric::BORROW_APR => apy.borrow_apr,
+            Metric::SUPPLY_GROSS => apy.supply_gross,
+        };
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        return Metric::all().into_iter()
+            .find(|metric| metric.field().eq_ignore_ascii_case(s))
+            .ok_or(format!("'{}' is not a valid value for Metric", s));
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Averages of the `metrics` of `assets` in `market` between `from` and `to`, per `bucket`
+#[derive(Debug, Clone)]
+pub struct HistoryQuery {
+    pub market: String,
+    /// Every asset when empty
+    pub assets: Vec<AssetSymbol>,
+    pub metrics: Vec<Metric>,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub bucket: Bucket,
+    /// Collection of data points the averages are calculated from
+    pub data_type: DataType,
+}
+
+/// Values of the requested metrics in a single data point, in the order of the query
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub date: DateTime<Utc>,
+    pub values: Vec<Option<f64>>,
+}
+
+/// Spread of a metric within a bucket. `high` and `low` are its max and min, `open` and `close`
+/// its first and last values.
+#[derive(Serialize, Debug, Clone)]
+pub struct Summary {
+    pub mean: f64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub std_dev: f64,
+    pub p50: f64,
+    pub p90: f64,
+}
+
+impl Summary {
+    /// Summarizes `values` in chronological order, none when there are no values
+    pub fn from_values(values: &[f64]) -> Option<Self> {
+        let (open, close) = (*values.first()?, *values.last()?);
+        let count = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / count;
+        let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / count;
+
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        return Some(Self {
+            mean,
+            open,
+            high: sorted[sorted.len() - 1],
+            low: sorted[0],
+            close,
+            std_dev: variance.sqrt(),
+            p50: percentile(&sorted, 0.5),
+            p90: percentile(&sorted, 0.9),
+        });
+    }
+}
+
+/// Linear interpolation between the closest ranks of the non-empty `sorted` values
+fn percentile(sorted: &[f64], percentile: f64) -> f64 {
+    let rank = percentile * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    return sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64);
+}
+
+/// Averages of a single bucket, metrics without any value in the bucket are null in `values` and
+/// missing from `stats`
+#[derive(Serialize, Debug, Clone)]
+pub struct HistoryPoint {
+    pub date: DateTime<Utc>,
+    /// Number of data points in the bucket
+    pub samples: u64,
+    #[serde(flatten)]
+    pub values: BTreeMap<Metric, Option<f64>>,
+    pub stats: BTreeMap<Metric, Summary>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AssetHistory {
+    pub asset: AssetSymbol,
+    /// Buckets with data, in chronological order
+    pub points: Vec<HistoryPoint>,
+}
+
+/// Query string of the history route, every parameter is optional
+#[derive(Deserialize, Debug, Default)]
+pub struct HistoryParams {
+    /// Comma separated asset symbols
+    pub asset: Option<String>,
+    /// RFC 3339 date or YYYY-MM-DD
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub bucket: Option<String>,
+    /// Comma separated metric names
+    pub metrics: Option<String>,
+    pub data: Option<String>,
+}
+
+impl HistoryQuery {
+    /// Builds the query of the history route: daily averages of the supply, borrow and price of
+    /// every asset over the last 7 days, from the hourly data points, unless stated otherwise
+    pub fn from_params(params: &HistoryParams, market: &Market) -> Result<Self> {
+        let mut assets = Vec::new();
+        for symbol in split_list(&params.asset) {
+            let asset = market.assets().into_iter()
+                .find(|a| a.0.eq_ignore_ascii_case(symbol))
+                .ok_or(Error::NotFound(format!("Asset {} in {} market", symbol, market.name)))?;
+            assets.push(asset);
+        }
+        let metrics = match &params.metrics {
+            Some(_) => split_list(&params.metrics).into_iter()
+                .map(|metric| Metric::from_str(metric).map_err(Error::InvalidRequest))
+                .collect::<Result<Vec<Metric>>>()?,
+            None => vec![Metric::SUPPLY, Metric::BORROW, Metric::PRICE],
+        };
+        let to = match &params.to {
+            Some(to) => parse_param_date(to, true)?,
+            None => Utc::now(),
+        };
+        let from = match &params.from {
+            Some(from) => parse_param_date(from, false)?,
+            None => to - Duration::days(DEFAULT_DAYS_BACK),
+        };
+        let bucket = match &params.bucket {
+            Some(bucket) => Bucket::from_str(bucket).map_err(Error::InvalidRequest)?,
+            None => Bucket::DAY,
+        };
+        let data_type = match &params.data {
+            Some(data_type) => DataType::from_str(data_type).map_err(Error::InvalidRequest)?,
+            None => DataType::HOUR,
+        };
+
+        let query = Self { market: market.name.clone(), assets, metrics, from, to, bucket, data_type };
+        query.validate()?;
+        return Ok(query);
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.from > self.to {
+            return Err(Error::InvalidRequest(format!("from {} is after to {}", self.from, self.to)));
+        }
+        if self.metrics.is_empty() {
+            return Err(Error::InvalidRequest("No metric requested".to_string()));
+        }
+        return Ok(());
+    }
+
+    pub fn includes(&self, asset: &AssetSymbol) -> bool {
+        return self.assets.is_empty() || self.assets.contains(asset);
+    }
+
+    /// Aggregates `data_points` in memory, for backends that cannot group them themselves. Data
+    /// points outside of the range or of other assets are ignored.
+    pub fn aggregate(&self, data_points: Vec<(DateTime<Utc>, Vec<APY>)>) -> Vec<AssetHistory> {
+        let mut buckets: HashMap<AssetSymbol, BTreeMap<DateTime<Utc>, Vec<Sample>>> = HashMap::new();
+        for (date, apys) in data_points {
+            if date < self.from || date > self.to {
+                continue;
+            }
+            let start = self.bucket.start(&date);
+            for apy in apys.iter().filter(|apy| self.includes(&apy.asset)) {
+                let values = self.metrics.iter().map(|metric| metric.value(apy)).collect();
+                buckets.entry(apy.asset.clone()).or_default().entry(start).or_default().push(Sample { date, values });
+            }
+        }
+
+        let points = buckets.into_iter().flat_map(|(asset, buckets)| {
+            buckets.into_iter().map(move |(date, samples)| (asset.clone(), self.point(date, samples)))
+        }).collect();
+        return self.collect(points);
+    }
+
+    /// Statistics of the bucket starting at `date` from its `samples`, in any order
+    pub fn point(&self, date: DateTime<Utc>, mut samples: Vec<Sample>) -> HistoryPoint {
+        samples.sort_by_key(|sample| sample.date);
+        let mut values = BTreeMap::new();
+        let mut stats = BTreeMap::new();
+        for (index, metric) in self.metrics.iter().enumerate() {
+            let metric_values: Vec<f64> = samples.iter().filter_map(|sample| sample.values.get(index).cloned().flatten()).collect();
+            let summary = Summary::from_values(&metric_values);
+            values.insert(*metric, summary.as_ref().map(|summary| summary.mean));
+            if let Some(summary) = summary {
+                stats.insert(*metric, summary);
+            }
+        }
+        return HistoryPoint { date, samples: samples.len() as u64, values, stats };
+    }
+
+    /// Groups the `points` of every asset, ordered as requested or by symbol
+    pub fn collect(&self, points: Vec<(AssetSymbol, HistoryPoint)>) -> Vec<AssetHistory> {
+        let mut history: Vec<AssetHistory> = Vec::new();
+        for (asset, point) in points {
+            match history.iter_mut().find(|h| h.asset == asset) {
+                Some(asset_history) => asset_history.points.push(point),
+                None => history.push(AssetHistory { asset, points: vec![point] }),
+            }
+        }
+        for asset_history in history.iter_mut() {
+            asset_history.points.sort_by_key(|point| point.date);
+        }
+        history.sort_by_key(|h| match self.assets.iter().position(|asset| *asset == h.asset) {
+            Some(index) => (index, String::new()),
+            None => (self.assets.len(), h.asset.0.clone()),
+        });
+        return history;
+    }
+}
+
+pub fn split_list(list: &Option<String>) -> Vec<&str> {
+    return match list {
+        Some(list) => list.split(',').map(str::trim).filter(|item| !item.is_empty()).collect(),
+        None => Vec::new(),
+    };
+}
+
+/// Plain dates cover the whole day, so they are taken at its start or at its end
+pub fn parse_param_date(date: &str, end_of_day: bool) -> Result<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+        return Ok(date.with_timezone(&Utc));
+    }
+    let day = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|e| Error::InvalidRequest(format!("Invalid date '{}': {}", date, e)))?;
+    let start = Utc.from_utc_date(&day).and_hms(0, 0, 0);
+    return Ok(if end_of_day { start + Duration::days(1) - Duration::seconds(1) } else { start });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() <= 1e-12, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn summary_of_odd_count() {
+        let summary = Summary::from_values(&[3.0, 1.0, 2.0]).unwrap();
+        assert_close(summary.mean, 2.0);
+        assert_close(summary.open, 3.0);
+        assert_close(summary.close, 2.0);
+        assert_close(summary.low, 1.0);
+        assert_close(summary.high, 3.0);
+        assert_close(summary.std_dev, (2f64 / 3f64).sqrt());
+        assert_close(summary.p50, 2.0);
+        assert_close(summary.p90, 2.8);
+    }
+
+    #[test]
+    fn summary_of_even_count() {
+        let summary = Summary::from_values(&[4.0, 1.0, 3.0, 2.0]).unwrap();
+        assert_close(summary.mean, 2.5);
+        assert_close(summary.open, 4.0);
+        assert_close(summary.close, 2.0);
+        assert_close(summary.low, 1.0);
+        assert_close(summary.high, 4.0);
+        assert_close(summary.std_dev, 1.25f64.sqrt());
+        // The median of an even count is halfway between the middle values
+        assert_close(summary.p50, 2.5);
+        assert_close(summary.p90, 3.7);
+    }
+
+    #[test]
+    fn summary_of_single_value() {
+        let summary = Summary::from_values(&[0.05]).unwrap();
+        for value in [summary.mean, summary.open, summary.close, summary.low, summary.high, summary.p50, summary.p90].iter() {
+            assert_close(*value, 0.05);
+        }
+        assert_close(summary.std_dev, 0.0);
+    }
+
+    #[test]
+    fn summary_of_nothing() {
+        assert!(Summary::from_values(&[]).is_none());
+    }
+
+    #[test]
+    fn percentile_interpolates_between_ranks() {
+        let sorted = [10.0, 20.0];
+        assert_close(percentile(&sorted, 0.0), 10.0);
+        assert_close(percentile(&sorted, 0.25), 12.5);
+        assert_close(percentile(&sorted, 0.5), 15.0);
+        assert_close(percentile(&sorted, 1.0), 20.0);
+        assert_close(percentile(&[7.0], 0.9), 7.0);
+    }
+
+    #[test]
+    fn buckets_across_day_and_year_boundaries() {
+        // A Friday, the last second of the year
+        let date = Utc.ymd(2021, 12, 31).and_hms(23, 59, 59);
+        let new_year = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let cases = [
+            (Bucket::HOUR, Utc.ymd(2021, 12, 31).and_hms(23, 0, 0), new_year),
+            (Bucket::DAY, Utc.ymd(2021, 12, 31).and_hms(0, 0, 0), new_year),
+            (Bucket::WEEK, Utc.ymd(2021, 12, 27).and_hms(0, 0, 0), Utc.ymd(2022, 1, 3).and_hms(0, 0, 0)),
+            (Bucket::MONTH, Utc.ymd(2021, 12, 1).and_hms(0, 0, 0), new_year),
+        ];
+        for (bucket, start, next) in cases.iter() {
+            assert_eq!(bucket.start(&date), *start, "{}", bucket);
+            assert_eq!(bucket.next(start), *next, "{}", bucket);
+            // The next bucket starts at its own start
+            assert_eq!(bucket.start(next), *next, "{}", bucket);
+            assert_eq!(bucket.start(&(*next - Duration::seconds(1))), *start, "{}", bucket);
+        }
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        let monday = Utc.ymd(2021, 10, 25).and_hms(0, 0, 0);
+        // Sunday night is still in the week of the Monday before
+        assert_eq!(Bucket::WEEK.start(&Utc.ymd(2021, 10, 31).and_hms(23, 30, 0)), monday);
+        assert_eq!(Bucket::WEEK.start(&monday), monday);
+        let next_monday = Bucket::WEEK.next(&monday);
+        assert_eq!(next_monday, Utc.ymd(2021, 11, 1).and_hms(0, 0, 0));
+        assert_eq!(Bucket::WEEK.start(&(next_monday + Duration::minutes(30))), next_monday);
+    }
+}
@@ -0,0 +1,175 @@
+use chrono::{TimeZone, Utc};
+use log::{info, warn};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use crate::apy::APY;
+use crate::db::{APYDataPoint, DataType, SharedStorage};
+use crate::error::{Error, Result};
+use crate::export::{ExportFormat, ExportRow};
+use crate::utils::{AssetSymbol, ProgramConfig};
+
+/// Number of invalid rows listed when an import is rejected
+const MAX_REPORTED_ERRORS: usize = 10;
+
+#[derive(Serialize, Debug, Default)]
+pub struct ImportReport {
+    pub rows: usize,
+    pub inserted: usize,
+    /// Rows whose date, asset and data type were already stored or appeared earlier in the file
+    pub duplicates: usize,
+}
+
+/// Format of `path` guessed from its extension, CSV unless it is a JSON Lines file
+pub fn format_from_path(path: &Path) -> ExportFormat {
+    return match path.extension().and_then(|extension| extension.to_str()) {
+        Some("ndjson") | Some("jsonl") => ExportFormat::NDJSON,
+        Some("parquet") => ExportFormat::PARQUET,
+        _ => ExportFormat::CSV,
+    };
+}
+
+/// Reads rows as written by the export. Every row is checked before anything is stored: an
+/// unknown market or asset rejects the whole file.
+pub fn read_rows(path: &Path, format: ExportFormat, program_config: &ProgramConfig) -> Result<Vec<ExportRow>> {
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    match format {
+        ExportFormat::CSV => {
+            let mut reader = csv::Reader::from_reader(File::open(path)?);
+            for (index, row) in reader.deserialize::<ExportRow>().enumerate() {
+                match row {
+                    Ok(row) => rows.push((index + 2, row)),
+                    Err(e) => errors.push(format!("line {}: {}", index + 2, e)),
+                }
+            }
+        },
+        ExportFormat::NDJSON => {
+            for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<ExportRow>(&line) {
+                    Ok(row) => rows.push((index + 1, row)),
+                    Err(e) => errors.push(format!("line {}: {}", index + 1, e)),
+                }
+            }
+        },
+        ExportFormat::PARQUET => return Err(Error::Config("Parquet files cannot be imported, export as CSV or NDJSON instead".to_string())),
+    }
+
+    for (line, row) in rows.iter_mut() {
+        // Symbols are stored as the program config spells them
+        let market = match program_config.market(&row.market) {
+            Some(market) => market,
+            None => {
+                errors.push(format!("line {}: unknown market {}", line, row.market));
+                continue;
+            },
+        };
+        match market.assets().into_iter().find(|asset| asset.0.eq_ignore_ascii_case(&row.asset.0)) {
+            Some(asset) => {
+                row.market = market.name.clone();
+                row.asset = asset;
+            },
+            None => errors.push(format!("line {}: unknown asset {} in {} market", line, row.asset, market.name)),
+        }
+    }
+
+    if !errors.is_empty() {
+        let count = errors.len();
+        errors.truncate(MAX_REPORTED_ERRORS);
+        return Err(Error::Decode(format!("{} invalid rows in {}: {}", count, path.display(), errors.join(", "))));
+    }
+    return Ok(rows.into_iter().map(|(_, row)| row).collect());
+}
+
+/// Groups `rows` back into data points and inserts those not stored yet, deduplicating on the
+/// date, asset and data type within the market. Stats are not part of the rows, imported data
+/// points have none.
+pub async fn import(storage: &SharedStorage, rows: Vec<ExportRow>) -> Result<ImportReport> {
+    let mut report = ImportReport { rows: rows.len(), ..ImportReport::default() };
+
+    // Rows of every data point, keyed by market, data type and date in milliseconds as stored
+    let mut data_points: BTreeMap<(String, String, i64), (DataType, Vec<ExportRow>)> = BTreeMap::new();
+    for row in rows {
+        let key = (row.market.clone(), row.data_type.to_string(), row.date.timestamp_millis());
+        data_points.entry(key).or_insert((row.data_type, Vec::new())).1.push(row);
+    }
+
+    // Assets already stored at every date, read once per market and data type over the range of the file
+    let mut stored: HashSet<(String, String, i64, AssetSymbol)> = HashSet::new();
+    let mut ranges: BTreeMap<(String, String), (DataType, i64, i64)> = BTreeMap::new();
+    for ((market, data_type_name, date), (data_type, _)) in data_points.iter() {
+        let range = ranges.entry((market.clone(), data_type_name.clone())).or_insert((*data_type, *date, *date));
+        range.1 = range.1.min(*date);
+        range.2 = range.2.max(*date);
+    }
+    for ((market, data_type_name), (data_type, from, to)) in ranges.iter() {
+        let from = Utc.timestamp_millis(*from);
+        let to = Utc.timestamp_millis(*to + 1);
+        for data_point in storage.get_datapoints_between(*data_type, market, from, to).await? {
+            for apy in data_point.apys.iter() {
+                stored.insert((market.clone(), data_type_name.clone(), data_point.date.timestamp_millis(), apy.asset.clone()));
+            }
+        }
+    }
+
+    let mut new_data_points = Vec::new();
+    for ((market, data_type_name, date), (data_type, rows)) in data_points {
+        let first = rows[0].clone();
+        let mut apys = Vec::new();
+        for row in rows {
+            if !stored.insert((market.clone(), data_type_name.clone(), date, row.asset.clone())) {
+                report.duplicates += 1;
+                continue;
+            }
+            let mut apy = APY {
+                market: row.market,
+                asset: row.asset,
+                name: row.name,
+                price: row.price,
+                supply: row.supply,
+                borrow: row.borrow,
+                supply_rewards: row.supply_rewards,
+                borrow_rewards: row.borrow_rewards,
+                weight_supply: row.weight_supply,
+                weight_borrow: row.weight_borrow,
+                mnde_supply_rewards: row.mnde_supply_rewards,
+                utilization: row.utilization,
+                supply_apr: row.supply_apr,
+                borrow_apr: row.borrow_apr,
+                supply_gross: row.supply_gross,
+            };
+            // Exports of older versions hold the APRs in `supply` and `borrow`
+            apy.upgrade_from_apr();
+            apys.push(apy);
+        }
+        if apys.is_empty() {
+            continue;
+        }
+        report.inserted += apys.len();
+        new_data_points.push(APYDataPoint {
+            date: first.date,
+            market,
+            data_type,
+            slot: first.slot,
+            apys,
+            stats: None,
+            backfilled: first.backfilled,
+        });
+    }
+
+    if report.duplicates > 0 {
+        warn!("{} duplicate rows skipped", report.duplicates);
+    }
+    storage.insert_datapoints(&new_data_points).await?;
+    info!("{} rows imported into {} data points", report.inserted, new_data_points.len());
+    return Ok(report);
+}
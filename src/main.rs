@@ -1,6 +1,20 @@
+// The code returns explicitly and names its enum variants in capitals, as the values they parse
+#![allow(clippy::needless_return, clippy::diverging_sub_expression, clippy::upper_case_acronyms, clippy::too_many_arguments)]
+
 mod apy;
+mod backfill;
 mod bot;
+mod curve;
 mod db;
+mod error;
+mod export;
+mod history;
+mod import;
+mod reserves;
+mod scheduler;
+mod snapshot;
+mod sources;
+mod table;
 mod utils;
 mod stats;
 
@@ -16,117 +30,400 @@ use actix_web::{
     rt,
     web,
 };
-use clap::{load_yaml, App as ClapApp};
+use clap::{load_yaml, App as ClapApp, ArgMatches};
 use chrono::Duration as chrono_Duration;
+use chrono::Utc;
 use dotenv::dotenv;
-use lazy_static::lazy_static;
 use log::{info, error};
-use solana_client::rpc_client::RpcClient;
+use serde::Serialize;
+use serde_json::json;
 use std::{
+    fs::File,
+    future::Future,
+    io::Write,
     path::Path,
     path::PathBuf,
     str::FromStr,
+    sync::Arc,
     sync::mpsc,
     thread,
-    time::Duration
 };
 
-use apy::APY;
 use db::DataType;
-use db::Database;
+use db::SharedStorage;
 use bot::TwitterBot;
 use bot::ScreenshotBot;
+use curve::{RateCurve, Simulation, SimulationParams};
+use error::{Error, ErrorBody, Result};
+use export::{ExportFormat, ExportParams, ExportQuery};
+use history::{HistoryParams, HistoryQuery};
+use reserves::{ReserveInfo, ReserveReport};
+use scheduler::{Scheduler, Task};
+use snapshot::{Cached, Snapshot, SnapshotCache};
+use sources::{FixtureSource, RpcSource, SharedSource};
+use table::{APYTable, TableColumn, TableFormat};
+use tokio::runtime::Handle;
 use utils::AssetSymbol;
 use utils::ChartData;
+use utils::{Cluster, DEFAULT_MARKET, Market, ProgramConfig};
 use utils::config;
-use stats::Stats;
 
-const RPC_URL: &str = "https://solana-api.projectserum.com/";
 const PRODUCTION_CONFIG_JSON: &str = include_str!("assets/production.json");
-const _DEVNET_CONFIG_JSON: &str = include_str!("assets/devnet.json");
-
-lazy_static! {
-#[rustfmt::skip]
-static ref PRODUCTION_ASSETS: Vec<AssetSymbol> = [
-        (AssetSymbol::SLND),
-        (AssetSymbol::SOL),
-        (AssetSymbol::USDC),
-        (AssetSymbol::USDT),
-        (AssetSymbol::ETH),
-        (AssetSymbol::weWETH),
-        (AssetSymbol::BTC),
-        (AssetSymbol::mSOL),
-        (AssetSymbol::SRM),
-        (AssetSymbol::FTT),
-        (AssetSymbol::RAY),
-        (AssetSymbol::SBR),
-        (AssetSymbol::MER),
-    ].iter().cloned().collect();
-}
+const DEVNET_CONFIG_JSON: &str = include_str!("assets/devnet.json");
 
 //=========================================================================================
 // API
 //=========================================================================================
+#[get("/markets")]
+async fn markets_route(program_config: web::Data<ProgramConfig>) -> impl Responder {
+    HttpResponse::Ok().json(&program_config.markets)
+}
+
 #[get("/apy")]
-async fn apy_route() -> impl Responder {
-    let client = RpcClient::new_with_timeout(RPC_URL.to_string(), Duration::from_secs(120));
-    let result = APY::from_assets(&client, &PRODUCTION_ASSETS);
-    HttpResponse::Ok().json(&result)
+async fn apy_route(cache: web::Data<SnapshotCache>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>) -> Result<HttpResponse> {
+    apy_response(cache.into_inner(), &program_config, DEFAULT_MARKET, &runtime).await
+}
+
+#[get("/markets/{market}/apy")]
+async fn market_apy_route(cache: web::Data<SnapshotCache>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>, param: web::Path<String>) -> Result<HttpResponse> {
+    apy_response(cache.into_inner(), &program_config, &param, &runtime).await
 }
 
 #[get("/apy/{asset_symbol}")]
-async fn apy_asset_route(param: web::Path<String>) -> impl Responder {
-    let client = RpcClient::new_with_timeout(RPC_URL.to_string(), Duration::from_secs(120));
-    let asset_symbol = AssetSymbol::from_str(&param.to_uppercase()).unwrap();
-    let apy = APY::from_asset(&client, asset_symbol);
-    HttpResponse::Ok().json(&apy)
+async fn apy_asset_route(cache: web::Data<SnapshotCache>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>, param: web::Path<String>) -> Result<HttpResponse> {
+    apy_asset_response(cache.into_inner(), &program_config, DEFAULT_MARKET, &param, &runtime).await
+}
+
+#[get("/markets/{market}/apy/{asset_symbol}")]
+async fn market_apy_asset_route(cache: web::Data<SnapshotCache>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>, param: web::Path<(String, String)>) -> Result<HttpResponse> {
+    let (market_name, asset_symbol) = param.into_inner();
+    apy_asset_response(cache.into_inner(), &program_config, &market_name, &asset_symbol, &runtime).await
+}
+
+#[get("/apy/{asset_symbol}/curve")]
+async fn apy_curve_route(cache: web::Data<SnapshotCache>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>, param: web::Path<String>) -> Result<HttpResponse> {
+    apy_curve_response(cache.into_inner(), &program_config, DEFAULT_MARKET, &param, &runtime).await
+}
+
+#[get("/markets/{market}/apy/{asset_symbol}/curve")]
+async fn market_apy_curve_route(cache: web::Data<SnapshotCache>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>, param: web::Path<(String, String)>) -> Result<HttpResponse> {
+    let (market_name, asset_symbol) = param.into_inner();
+    apy_curve_response(cache.into_inner(), &program_config, &market_name, &asset_symbol, &runtime).await
+}
+
+#[get("/apy/{asset_symbol}/simulate")]
+async fn apy_simulate_route(cache: web::Data<SnapshotCache>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>, param: web::Path<String>, params: web::Query<SimulationParams>) -> Result<HttpResponse> {
+    apy_simulate_response(cache.into_inner(), &program_config, DEFAULT_MARKET, &param, &params, &runtime).await
+}
+
+#[get("/markets/{market}/apy/{asset_symbol}/simulate")]
+async fn market_apy_simulate_route(cache: web::Data<SnapshotCache>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>, param: web::Path<(String, String)>, params: web::Query<SimulationParams>) -> Result<HttpResponse> {
+    let (market_name, asset_symbol) = param.into_inner();
+    apy_simulate_response(cache.into_inner(), &program_config, &market_name, &asset_symbol, &params, &runtime).await
+}
+
+#[get("/reserves")]
+async fn reserves_route(cache: web::Data<SnapshotCache>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>) -> Result<HttpResponse> {
+    reserves_response(cache.into_inner(), &program_config, DEFAULT_MARKET, &runtime).await
+}
+
+#[get("/markets/{market}/reserves")]
+async fn market_reserves_route(cache: web::Data<SnapshotCache>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>, param: web::Path<String>) -> Result<HttpResponse> {
+    reserves_response(cache.into_inner(), &program_config, &param, &runtime).await
+}
+
+#[get("/reserves/{asset_symbol}")]
+async fn reserve_asset_route(cache: web::Data<SnapshotCache>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>, param: web::Path<String>) -> Result<HttpResponse> {
+    reserve_asset_response(cache.into_inner(), &program_config, DEFAULT_MARKET, &param, &runtime).await
+}
+
+#[get("/markets/{market}/reserves/{asset_symbol}")]
+async fn market_reserve_asset_route(cache: web::Data<SnapshotCache>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>, param: web::Path<(String, String)>) -> Result<HttpResponse> {
+    let (market_name, asset_symbol) = param.into_inner();
+    reserve_asset_response(cache.into_inner(), &program_config, &market_name, &asset_symbol, &runtime).await
 }
 
 #[get("/info")]
-async fn info_route() -> impl Responder {
-    let client = RpcClient::new_with_timeout(RPC_URL.to_string(), Duration::from_secs(120));
-    let result = Stats::from_assets(&client, &PRODUCTION_ASSETS);
-    HttpResponse::Ok().json(&result)
+async fn info_route(cache: web::Data<SnapshotCache>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>) -> Result<HttpResponse> {
+    info_response(cache.into_inner(), &program_config, DEFAULT_MARKET, &runtime).await
+}
+
+#[get("/markets/{market}/info")]
+async fn market_info_route(cache: web::Data<SnapshotCache>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>, param: web::Path<String>) -> Result<HttpResponse> {
+    info_response(cache.into_inner(), &program_config, &param, &runtime).await
 }
 
 #[get("/chart_data")]
-async fn chart_data() -> impl Responder {
-    // TODO: Find a way to reuse the runtime
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    let mut result = Vec::new();
-    let async_block  = async { 
-        let database = Database::from_config(utils::Config::from_env().unwrap()).await;
-        result = database.get_daily_datapoints_as_avg(chrono_Duration::days(7)).await;
-    };
-    rt.block_on(async_block);
+async fn chart_data(database: Option<web::Data<SharedStorage>>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>) -> Result<HttpResponse> {
+    chart_data_response(database, &program_config, DEFAULT_MARKET, &runtime).await
+}
+
+#[get("/markets/{market}/chart_data")]
+async fn market_chart_data(database: Option<web::Data<SharedStorage>>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>, param: web::Path<String>) -> Result<HttpResponse> {
+    chart_data_response(database, &program_config, &param, &runtime).await
+}
+
+#[get("/history")]
+async fn history_route(database: Option<web::Data<SharedStorage>>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>, params: web::Query<HistoryParams>) -> Result<HttpResponse> {
+    history_response(database, &program_config, DEFAULT_MARKET, &params, &runtime).await
+}
+
+#[get("/markets/{market}/history")]
+async fn market_history_route(database: Option<web::Data<SharedStorage>>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>, param: web::Path<String>, params: web::Query<HistoryParams>) -> Result<HttpResponse> {
+    history_response(database, &program_config, &param, &params, &runtime).await
+}
+
+#[get("/export")]
+async fn export_route(database: Option<web::Data<SharedStorage>>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>, params: web::Query<ExportParams>) -> Result<HttpResponse> {
+    export_response(database, &program_config, DEFAULT_MARKET, &params, &runtime)
+}
+
+#[get("/markets/{market}/export")]
+async fn market_export_route(database: Option<web::Data<SharedStorage>>, program_config: web::Data<ProgramConfig>, runtime: web::Data<Handle>, param: web::Path<String>, params: web::Query<ExportParams>) -> Result<HttpResponse> {
+    export_response(database, &program_config, &param, &params, &runtime)
+}
+
+fn find_market<'a>(program_config: &'a ProgramConfig, market_name: &str) -> Result<&'a Market> {
+    return program_config.market(market_name).ok_or(Error::NotFound(format!("Market {}", market_name)));
+}
+
+/// Runs `future` on the shared tokio runtime. Actix runs the handlers on its own older runtime,
+/// which cannot drive reqwest or mongodb futures.
+async fn run_on<T, F>(runtime: &Handle, future: F) -> Result<T> where T: Send + 'static, F: Future<Output = Result<T>> + Send + 'static {
+    return runtime.spawn(future).await.map_err(std::io::Error::from)?;
+}
+
+async fn get_snapshot(cache: Arc<SnapshotCache>, market: &Market, runtime: &Handle) -> Result<Arc<Snapshot>> {
+    let market = market.clone();
+    return run_on(runtime, async move { cache.get(&market).await }).await;
+}
+
+async fn apy_response(cache: Arc<SnapshotCache>, program_config: &ProgramConfig, market_name: &str, runtime: &Handle) -> Result<HttpResponse> {
+    let market = find_market(program_config, market_name)?;
+    let snapshot = get_snapshot(cache, market, runtime).await?;
+    Ok(HttpResponse::Ok().json(Cached::new(&snapshot.apys, &snapshot)))
+}
+
+async fn apy_asset_response(cache: Arc<SnapshotCache>, program_config: &ProgramConfig, market_name: &str, asset_symbol: &str, runtime: &Handle) -> Result<HttpResponse> {
+    let market = find_market(program_config, market_name)?;
+    let asset_symbol = &find_asset(market, asset_symbol)?.asset;
+    let snapshot = get_snapshot(cache, market, runtime).await?;
+    match snapshot.apys.apys.iter().find(|apy| apy.asset == *asset_symbol) {
+        Some(apy) => Ok(HttpResponse::Ok().json(Cached::new(apy, &snapshot))),
+        None => Err(Error::Unavailable(format!("{} APY in {} market ({})", asset_symbol, market.name, snapshot.asset_error(asset_symbol)))),
+    }
+}
+
+async fn apy_curve_response(cache: Arc<SnapshotCache>, program_config: &ProgramConfig, market_name: &str, asset_symbol: &str, runtime: &Handle) -> Result<HttpResponse> {
+    let market = find_market(program_config, market_name)?;
+    let reserve_config = find_asset(market, asset_symbol)?;
+    let asset_symbol = &reserve_config.asset;
+    let snapshot = get_snapshot(cache, market, runtime).await?;
+    let reserve = snapshot.reserve(reserve_config)?;
+    let curve = RateCurve::from_reserve(reserve, market, asset_symbol)?;
+    Ok(HttpResponse::Ok().json(Cached::new(&curve, &snapshot)))
+}
+
+async fn apy_simulate_response(cache: Arc<SnapshotCache>, program_config: &ProgramConfig, market_name: &str, asset_symbol: &str, params: &SimulationParams, runtime: &Handle) -> Result<HttpResponse> {
+    let market = find_market(program_config, market_name)?;
+    let reserve_config = find_asset(market, asset_symbol)?;
+    let asset_symbol = &reserve_config.asset;
+    let snapshot = get_snapshot(cache, market, runtime).await?;
+    let reserve = snapshot.reserve(reserve_config)?;
+    let simulation = Simulation::from_snapshot(&snapshot, reserve, program_config, market, asset_symbol, params)?;
+    Ok(HttpResponse::Ok().json(Cached::new(&simulation, &snapshot)))
+}
+
+async fn reserves_response(cache: Arc<SnapshotCache>, program_config: &ProgramConfig, market_name: &str, runtime: &Handle) -> Result<HttpResponse> {
+    let market = find_market(program_config, market_name)?;
+    let snapshot = get_snapshot(cache, market, runtime).await?;
+    let report = ReserveReport::from_snapshot(&snapshot, program_config, market);
+    Ok(HttpResponse::Ok().json(Cached::new(&report, &snapshot)))
+}
+
+async fn reserve_asset_response(cache: Arc<SnapshotCache>, program_config: &ProgramConfig, market_name: &str, asset_symbol: &str, runtime: &Handle) -> Result<HttpResponse> {
+    let market = find_market(program_config, market_name)?;
+    let reserve_config = find_asset(market, asset_symbol)?;
+    let snapshot = get_snapshot(cache, market, runtime).await?;
+    let reserve = snapshot.reserve(reserve_config)?;
+    let reserve_info = ReserveInfo::from_reserve(reserve, program_config, market)?;
+    Ok(HttpResponse::Ok().json(Cached::new(&reserve_info, &snapshot)))
+}
+
+/// Reserve of the asset given by its symbol or its mint address
+fn find_asset<'a>(market: &'a Market, asset: &str) -> Result<&'a utils::Reserve> {
+    return market.find_reserve(asset)
+        .ok_or(Error::NotFound(format!("Asset {} in {} market", asset, market.name)));
+}
+
+async fn info_response(cache: Arc<SnapshotCache>, program_config: &ProgramConfig, market_name: &str, runtime: &Handle) -> Result<HttpResponse> {
+    let market = find_market(program_config, market_name)?;
+    let snapshot = get_snapshot(cache, market, runtime).await?;
+    Ok(HttpResponse::Ok().json(Cached::new(&snapshot.stats, &snapshot)))
+}
+
+async fn chart_data_response(database: Option<web::Data<SharedStorage>>, program_config: &ProgramConfig, market_name: &str, runtime: &Handle) -> Result<HttpResponse> {
+    let market = find_market(program_config, market_name)?;
+    let database: Arc<SharedStorage> = Arc::clone(&*database.ok_or(Error::Unavailable("Database".to_string()))?);
+
+    let database_market_name = market.name.clone();
+    let result = run_on(runtime, async move {
+        database.get_daily_datapoints_as_avg(&database_market_name, chrono_Duration::days(7)).await
+    }).await?;
 
     // Process data for Vue charting
     let mut chart_data_borrow_vec : Vec<ChartData> = Vec::new();
     let mut chart_data_supply_vec : Vec<ChartData> = Vec::new();
-    for &asset_symbol in PRODUCTION_ASSETS.iter() {
-        let mut data_points_borrow = Vec::new();
-        let mut data_points_supply = Vec::new();
-        let index : usize = result.iter().position(|e| { e.name == asset_symbol } ).unwrap();
-        for s in &result[index].supply {
-            let supply_value = f64::trunc(s[1].parse::<f64>().unwrap() * 10000.0) / 100.0;
-            data_points_supply.push((s[0].to_string(), supply_value));
-        }
-        for s in &result[index].borrow {
-            let borrow_value = f64::trunc(s[1].parse::<f64>().unwrap() * 10000.0) / 100.0;
-            data_points_borrow.push((s[0].to_string(), borrow_value));
-        }
-        let chart_data_borrow = ChartData { name: asset_symbol, data: data_points_borrow, };
-        let chart_data_supply= ChartData { name: asset_symbol, data: data_points_supply, };
+    for asset_symbol in market.assets() {
+        let index : usize = match result.iter().position(|e| { e.name == asset_symbol } ) {
+            Some(index) => index,
+            None => continue,
+        };
+        let chart_data_borrow = chart_data_series(asset_symbol.clone(), &result[index].borrow)?;
+        let chart_data_supply = chart_data_series(asset_symbol, &result[index].supply)?;
         chart_data_supply_vec.push(chart_data_supply);
         chart_data_borrow_vec.push(chart_data_borrow);
     }
 
-    HttpResponse::Ok().json((&chart_data_supply_vec, &chart_data_borrow_vec))
+    Ok(HttpResponse::Ok().json((&chart_data_supply_vec, &chart_data_borrow_vec)))
+}
+
+async fn history_response(database: Option<web::Data<SharedStorage>>, program_config: &ProgramConfig, market_name: &str, params: &HistoryParams, runtime: &Handle) -> Result<HttpResponse> {
+    let market = find_market(program_config, market_name)?;
+    let query = HistoryQuery::from_params(params, market)?;
+    let database: Arc<SharedStorage> = Arc::clone(&*database.ok_or(Error::Unavailable("Database".to_string()))?);
+
+    let history = run_on(runtime, async move { database.get_history(&query).await }).await?;
+    Ok(HttpResponse::Ok().json(&history))
+}
+
+/// Streams the rows as they are read from the database, a day of data points at a time
+fn export_response(database: Option<web::Data<SharedStorage>>, program_config: &ProgramConfig, market_name: &str, params: &ExportParams, runtime: &Handle) -> Result<HttpResponse> {
+    let market = find_market(program_config, market_name)?;
+    let query = ExportQuery::from_params(params, market)?;
+    let database: Arc<SharedStorage> = Arc::clone(&*database.ok_or(Error::Unavailable("Database".to_string()))?);
+
+    let (sender, receiver) = futures::channel::mpsc::unbounded::<Result<web::Bytes>>();
+    let export_query = query.clone();
+    runtime.spawn(async move {
+        let result = export::export(database.as_ref(), &export_query, |chunk| {
+            sender.unbounded_send(Ok(web::Bytes::from(chunk))).map_err(|_| Error::Unavailable("Export stream".to_string()))
+        }).await;
+        // The status is already sent, failures can only cut the body short
+        if let Err(e) = result {
+            error!("Could not export data: {}", e);
+            let _ = sender.unbounded_send(Err(e));
+        }
+    });
+
+    let file_name = format!("{}-{}.{}", query.market, query.data_type.to_string().to_lowercase(), query.format.extension());
+    Ok(HttpResponse::Ok()
+        .content_type(query.format.content_type())
+        .header("Content-Disposition", format!("attachment; filename=\"{}\"", file_name))
+        .streaming(receiver))
+}
+
+/// Means, lows and highs of `series` as percentages, the lows and highs are the means in series
+/// stored without them
+fn chart_data_series(name: AssetSymbol, series: &[Vec<String>]) -> Result<ChartData> {
+    let mut series_data = ChartData { name, data: Vec::new(), low: Vec::new(), high: Vec::new() };
+    for s in series {
+        let percent = |index: usize| -> Result<f64> {
+            let value = s.get(index).or(s.get(1)).ok_or(Error::Decode(format!("Chart point without value: {:?}", s)))?;
+            return Ok(f64::trunc(parse_chart_value(value)? * 10000.0) / 100.0);
+        };
+        series_data.data.push((s[0].to_string(), percent(1)?));
+        series_data.low.push((s[0].to_string(), percent(2)?));
+        series_data.high.push((s[0].to_string(), percent(3)?));
+    }
+    return Ok(series_data);
+}
+
+fn parse_chart_value(value: &str) -> Result<f64> {
+    return value.parse::<f64>().map_err(|e| Error::Decode(format!("Invalid chart value '{}': {}", value, e)));
 }
 
 //=========================================================================================
 // ENTRY POINT
 //=========================================================================================
+/// Exit code of invalid arguments, the same as a command failing on its configuration
+const EXIT_USAGE: i32 = 2;
+
+/// What a command prints on the standard output once it succeeds: `value` with --json, `message`
+/// otherwise. Nothing is printed when the command wrote its own output there.
+struct CommandOutput {
+    message: String,
+    value: serde_json::Value,
+}
+
+impl CommandOutput {
+    fn new<T: Serialize>(message: String, value: &T) -> Result<Self> {
+        return Ok(Self { message, value: serde_json::to_value(value)? });
+    }
+
+    fn none() -> Self {
+        return Self { message: String::new(), value: serde_json::Value::Null };
+    }
+}
+
+/// Loaded once for every command from .env and the global options
+struct Context {
+    config: utils::Config,
+    cluster: Cluster,
+    program_config: Arc<ProgramConfig>,
+    /// Single runtime shared by the data layer, the web server handlers and the bot tasks
+    rt: tokio::runtime::Runtime,
+}
+
+impl Context {
+    fn load(matches: &ArgMatches) -> Result<Self> {
+        let config = utils::Config::from_env()?;
+        info!("Configuration imported from .env");
+
+        let rt = tokio::runtime::Runtime::new()?;
+
+        // The cluster selects both the program config and the RPC node, the CLI takes precedence over .env
+        let cluster = matches.value_of("cluster").or(config.solana.cluster.as_deref()).unwrap_or("mainnet");
+        let cluster = Cluster::from_str(cluster).map_err(Error::Config)?;
+        let cache_path = match &config.solana.program_config_cache {
+            Some(cache_path) => PathBuf::from(cache_path),
+            None => cluster.default_cache_path(),
+        };
+        let program_config = rt.block_on(ProgramConfig::load(cluster, config.solana.program_config.as_deref(), &cache_path))?;
+        info!("Program config loaded for {}", cluster);
+
+        return Ok(Self { config, cluster, program_config: Arc::new(program_config), rt });
+    }
+
+    fn market(&self, matches: &ArgMatches) -> Result<&Market> {
+        return find_market(&self.program_config, matches.value_of("market").unwrap());
+    }
+
+    /// Market data comes from the cluster unless a recorded fixture is given
+    fn source(&self, matches: &ArgMatches) -> Result<Arc<SharedSource>> {
+        if let Some(fixture_dir) = matches.value_of("fixture") {
+            return Ok(Arc::new(FixtureSource::new(PathBuf::from(fixture_dir))));
+        }
+        let rpc_source = RpcSource::new(&self.config.rpc.endpoints(self.cluster), self.config.rpc.timeout())?;
+        return Ok(Arc::new(rpc_source));
+    }
+
+    fn database(&self) -> Result<Arc<SharedStorage>> {
+        return self.rt.block_on(db::storage_from_config(&self.config));
+    }
+
+    /// The routes answer 503 without a database, the commands serving them start anyway
+    fn optional_database(&self) -> Option<Arc<SharedStorage>> {
+        return match self.database() {
+            Ok(database) => Some(database),
+            Err(e) => {
+                error!("Could not open database: {}", e);
+                None
+            },
+        };
+    }
+}
+
 fn main() {
     std::env::set_var("RUST_BACKTRACE", "1");
     std::env::set_var("RUST_LOG", "solend_apy_bot=info");
@@ -134,106 +431,459 @@ fn main() {
     dotenv().ok();
 
     let yaml = load_yaml!("assets/cli.yml");
-    let matches = ClapApp::from_yaml(yaml).get_matches();
+    let matches = match ClapApp::from_yaml(yaml).get_matches_safe() {
+        Ok(matches) => matches,
+        // The help and the version are not errors
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            eprintln!("{}", e.message);
+            std::process::exit(EXIT_USAGE);
+        },
+    };
+    // Global options are propagated to the subcommand, which is required
+    let (command, matches) = match matches.subcommand() {
+        (command, Some(matches)) => (command, matches),
+        _ => unreachable!(),
+    };
     info!("Arguments parsed");
 
-    let config = utils::Config::from_env().unwrap();
-    info!("Configuration imported from .env");
+    let json = matches.is_present("json");
+    match run_command(command, matches) {
+        Ok(output) => {
+            if json && !output.value.is_null() {
+                println!("{}", output.value);
+            } else if !json && !output.message.is_empty() {
+                println!("{}", output.message);
+            }
+        },
+        Err(e) => {
+            error!("Could not run {}: {}", command, e);
+            if json {
+                let body = ErrorBody { error: e.kind().to_string(), message: e.to_string() };
+                println!("{}", serde_json::to_string(&body).unwrap());
+            }
+            std::process::exit(e.exit_code());
+        },
+    }
+}
 
-    // Start WebServer
-    let (tx, rx) = mpsc::channel();
-    thread::spawn(move || {
-        let _ = server_app(tx);
+fn run_command(command: &str, matches: &ArgMatches) -> Result<CommandOutput> {
+    let context = Context::load(matches)?;
+    return match command {
+        "serve" => serve_command(&context, matches),
+        "daemon" => daemon_command(&context, matches),
+        "snapshot" => snapshot_command(&context, matches),
+        "record" => record_command(&context, matches),
+        "table" => table_command(&context, matches),
+        "render" => render_command(&context, matches),
+        "post" => post_command(&context, matches),
+        "history" => history_command(&context, matches),
+        "export" => export_command(&context, matches),
+        "import" => import_command(&context, matches),
+        "migrate" => migrate_command(&context),
+        "rollup" => rollup_command(&context, matches),
+        "gaps" => gaps_command(&context, matches),
+        "backfill" => backfill_command(&context, matches),
+        _ => unreachable!(),
+    };
+}
+
+fn serve_command(context: &Context, matches: &ArgMatches) -> Result<CommandOutput> {
+    let cache = Arc::new(SnapshotCache::new(context.source(matches)?, context.program_config.clone()));
+    SnapshotCache::spawn_poller(cache.clone(), context.rt.handle(), context.config.snapshot.interval());
+    let srv = start_server(context.config.server()?, cache, context.program_config.clone(), context.optional_database(), context.rt.handle().clone())?;
+
+    let ctrlc_rx = ctrlc_receiver()?;
+    ctrlc_rx.recv().expect("Could not receive signal from channel");
+    stop_server(Some(srv));
+    return Ok(CommandOutput::none());
+}
+
+/// Runs the scheduled jobs until CTRL-C, the server and the database connection are shared between runs
+fn daemon_command(context: &Context, matches: &ArgMatches) -> Result<CommandOutput> {
+    let market = context.market(matches)?;
+    let mut scheduler = Scheduler::from_config(&context.config.schedule)?;
+    let database = context.optional_database();
+
+    // The market snapshots served by the routes are kept up to date
+    let cache = Arc::new(SnapshotCache::new(context.source(matches)?, context.program_config.clone()));
+    SnapshotCache::spawn_poller(cache.clone(), context.rt.handle(), context.config.snapshot.interval());
+    let srv = start_server(context.config.server()?, cache.clone(), context.program_config.clone(), database.clone(), context.rt.handle().clone())?;
+
+    let ctrlc_rx = match ctrlc_receiver() {
+        Ok(ctrlc_rx) => ctrlc_rx,
+        Err(e) => {
+            stop_server(Some(srv));
+            return Err(e);
+        },
+    };
+    info!("Daemon started");
+    scheduler.run(&ctrlc_rx, |task| run_task(task, context, market, &cache, database.as_deref()));
+
+    stop_server(Some(srv));
+    return Ok(CommandOutput::none());
+}
+
+fn snapshot_command(context: &Context, matches: &ArgMatches) -> Result<CommandOutput> {
+    let market = context.market(matches)?;
+    let data_type = DataType::from_str(matches.value_of("data").unwrap()).unwrap();
+    let database = context.database()?;
+    let cache = SnapshotCache::new(context.source(matches)?, context.program_config.clone());
+
+    let snapshot = save_data(context, &cache, market, data_type, database.as_ref())?;
+    let message = format!("{} data point of {} market saved at slot {}", data_type, market.name, snapshot.slot);
+    return CommandOutput::new(message, snapshot.as_ref());
+}
+
+fn record_command(context: &Context, matches: &ArgMatches) -> Result<CommandOutput> {
+    let market = context.market(matches)?;
+    let record_dir = matches.value_of("dir").unwrap();
+    let source = context.source(matches)?;
+
+    let reserve_pks = market.reserve_pubkeys(&market.assets())?;
+    context.rt.block_on(FixtureSource::record(source.as_ref(), &reserve_pks, PathBuf::from(record_dir)))?;
+    let message = format!("Fixture of {} market recorded in {}", market.name, record_dir);
+    return CommandOutput::new(message, &json!({ "market": market.name, "dir": record_dir }));
+}
+
+/// Prints the APYs of the market from a fresh snapshot, without the web server or Chrome
+fn table_command(context: &Context, matches: &ArgMatches) -> Result<CommandOutput> {
+    let market = context.market(matches)?;
+    let source = context.source(matches)?;
+
+    let snapshot = context.rt.block_on(Snapshot::from_source(source.as_ref(), &context.program_config, market))?;
+    let mut table = APYTable::new(&snapshot);
+    if let Some(column) = matches.value_of("sort") {
+        table.sort(TableColumn::from_str(column).unwrap(), matches.is_present("reverse"));
+    }
+    let format = TableFormat::from_str(matches.value_of("format").unwrap()).unwrap();
+    return CommandOutput::new(table.render(format)?, &table);
+}
+
+fn render_command(context: &Context, matches: &ArgMatches) -> Result<CommandOutput> {
+    let image_paths = render(context, matches)?;
+    let message = image_paths.iter().map(|image_path| image_path.display().to_string()).collect::<Vec<String>>().join("\n");
+    return CommandOutput::new(message, &json!({ "images": image_paths }));
+}
+
+/// Fails once every screenshot that could be taken is tweeted, when any screenshot or tweet failed
+fn post_command(context: &Context, matches: &ArgMatches) -> Result<CommandOutput> {
+    // Nothing is rendered without the Twitter credentials
+    context.config.twitter()?;
+    let image_paths = render(context, matches)?;
+    tweet_images(&context.config, &context.rt, &image_paths)?;
+    let message = format!("{} screenshots posted", image_paths.len());
+    return CommandOutput::new(message, &json!({ "images": image_paths }));
+}
+
+/// Screenshots the pages selected in `matches` from a local web server, started for them
+fn render(context: &Context, matches: &ArgMatches) -> Result<Vec<PathBuf>> {
+    let market = context.market(matches)?;
+    let task = Task {
+        data_type: matches.value_of("data").map(|data_type| DataType::from_str(data_type).unwrap()),
+        screenshot: matches.is_present("apy"),
+        charts: matches.is_present("charts"),
+        ..Task::default()
+    };
+    let database = context.optional_database();
+    let cache = Arc::new(SnapshotCache::new(context.source(matches)?, context.program_config.clone()));
+
+    // A fresh snapshot is stored, the screenshots taken next show the same numbers
+    if let Some(data_type) = task.data_type {
+        let database = database.as_ref().ok_or(Error::Unavailable("Database".to_string()))?;
+        save_data(context, &cache, market, data_type, database.as_ref())?;
+    }
+
+    let srv = start_server(context.config.server()?, cache, context.program_config.clone(), database, context.rt.handle().clone())?;
+    let mut image_paths = Vec::new();
+    let result = take_screenshots(&context.config, &task, &mut image_paths);
+    stop_server(Some(srv));
+    result?;
+    return Ok(image_paths);
+}
+
+fn history_command(context: &Context, matches: &ArgMatches) -> Result<CommandOutput> {
+    let market = context.market(matches)?;
+    let params = HistoryParams {
+        asset: matches.value_of("asset").map(str::to_string),
+        from: matches.value_of("from").map(str::to_string),
+        to: matches.value_of("to").map(str::to_string),
+        bucket: matches.value_of("bucket").map(str::to_string),
+        metrics: matches.value_of("metrics").map(str::to_string),
+        data: matches.value_of("data").map(str::to_string),
+    };
+    let query = HistoryQuery::from_params(&params, market)?;
+    let database = context.database()?;
+
+    let history = context.rt.block_on(database.get_history(&query))?;
+    return CommandOutput::new(serde_json::to_string_pretty(&history)?, &history);
+}
+
+fn export_command(context: &Context, matches: &ArgMatches) -> Result<CommandOutput> {
+    let market = context.market(matches)?;
+    let path = matches.value_of("file").unwrap();
+    let params = ExportParams {
+        asset: matches.value_of("asset").map(str::to_string),
+        from: matches.value_of("from").map(str::to_string),
+        to: matches.value_of("to").map(str::to_string),
+        format: matches.value_of("format").map(str::to_string),
+        data: matches.value_of("data").map(str::to_string),
+    };
+    let database = context.database()?;
+
+    let count = export_data(database.as_ref(), market, &params, path, &context.rt)?;
+    info!("{} rows exported to {}", count, path);
+    if path == "-" {
+        return Ok(CommandOutput::none());
+    }
+    return CommandOutput::new(format!("{} rows exported to {}", count, path), &json!({ "rows": count, "file": path }));
+}
+
+/// Rows name their own market, the import is not restricted to --market
+fn import_command(context: &Context, matches: &ArgMatches) -> Result<CommandOutput> {
+    let path = Path::new(matches.value_of("file").unwrap());
+    let format = match matches.value_of("format") {
+        Some(format) => ExportFormat::from_str(format).unwrap(),
+        None => import::format_from_path(path),
+    };
+    let database = context.database()?;
+
+    let rows = import::read_rows(path, format, &context.program_config)?;
+    let report = context.rt.block_on(import::import(database.as_ref(), rows))?;
+    let message = format!("{} of {} rows imported from {}, {} duplicates skipped", report.inserted, report.rows, path.display(), report.duplicates);
+    return CommandOutput::new(message, &report);
+}
+
+fn migrate_command(context: &Context) -> Result<CommandOutput> {
+    let database = context.database()?;
+    context.rt.block_on(database.migrate())?;
+    return CommandOutput::new("Database migrated".to_string(), &json!({ "migrated": true }));
+}
+
+fn rollup_command(context: &Context, matches: &ArgMatches) -> Result<CommandOutput> {
+    let market = context.market(matches)?;
+    let database = context.database()?;
+    rollup_data(context, market, None, database.as_ref())?;
+    return CommandOutput::new(format!("Data points of {} market rolled up", market.name), &json!({ "market": market.name }));
+}
+
+fn gaps_command(context: &Context, matches: &ArgMatches) -> Result<CommandOutput> {
+    let market = context.market(matches)?;
+    let data_type = DataType::from_str(matches.value_of("data").unwrap()).unwrap();
+    let days = matches.value_of("days").unwrap();
+    let days = days.parse::<i64>().map_err(|e| Error::Config(format!("Invalid number of days '{}': {}", days, e)))?;
+    let database = context.database()?;
+
+    let gaps = context.rt.block_on(backfill::find_gaps(database.as_ref(), data_type, &market.name, Utc::now() - chrono_Duration::days(days), Utc::now()))?;
+    info!("{} gaps found in the {} data points of {} market over the last {} days", gaps.len(), data_type, market.name, days);
+    let message = gaps.iter().map(|gap| format!("{} {}", gap.from.to_rfc3339(), gap.to.to_rfc3339())).collect::<Vec<String>>().join("\n");
+    return CommandOutput::new(message, &gaps);
+}
+
+fn backfill_command(context: &Context, matches: &ArgMatches) -> Result<CommandOutput> {
+    let market = context.market(matches)?;
+    let data_type = DataType::from_str(matches.value_of("data").unwrap()).unwrap();
+    let archive_dir = matches.value_of("dir").unwrap();
+    let database = context.database()?;
+
+    let count = context.rt.block_on(backfill::backfill(database.as_ref(), &context.program_config, market, data_type, Path::new(archive_dir), Utc::now()))?;
+    let message = format!("{} {} data points of {} market backfilled", count, data_type, market.name);
+    return CommandOutput::new(message, &json!({ "backfilled": count }));
+}
+
+fn ctrlc_receiver() -> Result<mpsc::Receiver<()>> {
+    let (ctrlc_tx, ctrlc_rx) = mpsc::channel();
+    ctrlc::set_handler(move || ctrlc_tx.send(())
+        .expect("Could not send signal on channel"))
+        .map_err(|e| Error::Config(format!("Could not set CTRL-C handler: {}", e)))?;
+    return Ok(ctrlc_rx);
+}
+
+/// Writes the export to `path`, or to the standard output when it is `-`
+fn export_data(database: &SharedStorage, market: &Market, params: &ExportParams, path: &str, rt: &tokio::runtime::Runtime) -> Result<usize> {
+    let query = ExportQuery::from_params(params, market)?;
+    let mut output: Box<dyn Write> = if path == "-" { Box::new(std::io::stdout()) } else { Box::new(File::create(path)?) };
+    let count = rt.block_on(export::export(database, &query, |chunk| Ok(output.write_all(&chunk)?)))?;
+    output.flush()?;
+    return Ok(count);
+}
+
+/// Rolls up the `target` tier, or every tier when none is given, then prunes the old data points
+fn rollup_data(context: &Context, market: &Market, target: Option<DataType>, database: &SharedStorage) -> Result<()> {
+    return context.rt.block_on(async {
+        db::rollup(database, &market.name, target, Utc::now()).await?;
+        db::apply_retention(database, &context.config.retention, Utc::now()).await
     });
-    let srv = rx.recv().unwrap();
+}
 
-    if matches.is_present("server") {
-        let (ctrlc_tx, ctrlc_rx) = mpsc::channel();
+/// Refreshes the snapshot of `market` and stores it as a data point
+fn save_data(context: &Context, cache: &SnapshotCache, market: &Market, data_type: DataType, database: &SharedStorage) -> Result<Arc<Snapshot>> {
+    let snapshot = context.rt.block_on(cache.refresh(market))?;
+    context.rt.block_on(database.save_snapshot(&snapshot, data_type))?;
+    return Ok(snapshot);
+}
 
-        ctrlc::set_handler(move || ctrlc_tx.send(())
-            .expect("Could not send signal on channel"))
-            .expect("Error setting CTRL-C handler");
+/// Takes the screenshots of `task` into `image_paths`. A failed screenshot does not stop the
+/// others, the last failure is returned once they are all taken.
+fn take_screenshots(config: &utils::Config, task: &Task, image_paths: &mut Vec<PathBuf>) -> Result<()> {
+    let screenshot_bot = ScreenshotBot::from_config(config.server()?).map_err(|e| Error::Config(e.to_string()))?;
+    let mut screenshots = Vec::new();
+    if task.screenshot {
+        screenshots.push(("/", ".b-aspect-content"));
+    }
+    if task.charts {
+        screenshots.push(("/charts", ".row.supply_chart"));
+        screenshots.push(("/charts", ".row.borrow_chart"));
+    }
 
-        // Keep server alive for debugging purposes
-        ctrlc_rx.recv().expect("Could not receive singal from channel.");
-        // Close WebServer
-        rt::System::new("").block_on(srv.stop(true));
-        info!("Server closed");
-        return;
+    let mut result = Ok(());
+    for (path, element) in screenshots {
+        match screenshot_bot.take_screenshot(path.to_string(), element.to_string()) {
+            Ok(image_path) => image_paths.push(image_path),
+            Err(e) => {
+                error!("Could not take screenshot of {} in {}: {}", element, path, e);
+                result = Err(Error::Unavailable(format!("Screenshot of {} in {} ({})", element, path, e)));
+            },
+        }
     }
+    return result;
+}
 
-    let rt = tokio::runtime::Runtime::new().unwrap();
+/// Tweets every image, the last failure is returned once they are all sent
+fn tweet_images(config: &utils::Config, rt: &tokio::runtime::Runtime, image_paths: &[PathBuf]) -> Result<()> {
+    let twitter_bot = TwitterBot::from_config(config.twitter()?);
+    return rt.block_on(async {
+        let mut result = Ok(());
+        for image_path in image_paths {
+            if let Err(e) = twitter_bot.tweet(image_path).await {
+                error!("Could not tweet {:?}: {}", image_path, e);
+                result = Err(Error::Unavailable(format!("Tweet of {:?} ({})", image_path, e)));
+            }
+        }
+        result
+    });
+}
+
+/// Runs a scheduled job, failures are logged and the daemon goes on with the next one
+fn run_task(task: &Task, context: &Context, market: &Market, cache: &SnapshotCache, database: Option<&SharedStorage>) {
     // Save Data in database
     {
-        if let Some(data_type) = matches.value_of("data") {
-            let async_block = async {
-                let database = Database::from_config(config.clone()).await;
-                let data_type = DataType::from_str(&data_type).unwrap();
-                database.save_apys_in_database(config.clone(), data_type).await;
+        if let Some(data_type) = task.data_type {
+            // A fresh snapshot is stored, the screenshots taken next show the same numbers
+            let result = match database {
+                Some(database) => save_data(context, cache, market, data_type, database),
+                None => Err(Error::Unavailable("Database".to_string())),
             };
-            rt.block_on(async_block);
+            if let Err(e) = result {
+                error!("Could not save data in database: {}", e);
+            }
+        }
+    }
+
+    // Roll up data in database
+    {
+        if let Some(target) = task.rollup {
+            let result = match database {
+                Some(database) => rollup_data(context, market, Some(target), database),
+                None => Err(Error::Unavailable("Database".to_string())),
+            };
+            if let Err(e) = result {
+                error!("Could not roll up data in database: {}", e);
+            }
         }
     }
 
     // Take Screenshot
     let mut image_paths = Vec::<PathBuf>::new();
     {
-        let screenshot_bot = ScreenshotBot::from_config(config.clone()).unwrap();
-        if matches.is_present("screenshot") {
-            image_paths.push(screenshot_bot.take_screenshot("/".to_string(), ".b-aspect-content".to_string()).unwrap());
-        }
-        if matches.is_present("charts") {
-            image_paths.push(screenshot_bot.take_screenshot("/charts".to_string(), ".row.supply_chart".to_string()).unwrap());
-            image_paths.push(screenshot_bot.take_screenshot("/charts".to_string(), ".row.borrow_chart".to_string()).unwrap());
+        if let Err(e) = take_screenshots(&context.config, task, &mut image_paths) {
+            error!("Could not take every screenshot: {}", e);
         }
     }
 
-    // Close WebServer
-    rt::System::new("").block_on(srv.stop(true));
-    info!("Server closed");
-
     // Tweet screenshot
     {
-        if matches.is_present("twitter") {
-            if !matches.is_present("charts") && !matches.is_present("screenshot") {
-                error!("--twitter needs to be called with either --charts or --screenshot");
-            } else {
-                let twitter_bot = TwitterBot::from_config(config.clone());
-                let async_block = async {
-                    for image_path in image_paths {
-                        twitter_bot.tweet(&image_path).await.unwrap();
-                    }
-                };
-                rt.block_on(async_block);
+        if task.twitter {
+            if let Err(e) = tweet_images(&context.config, &context.rt, &image_paths) {
+                error!("Could not tweet every screenshot: {}", e);
             }
         }
     }
+}
+
+/// Starts the server on its own thread, once it is bound to the address of the config
+fn start_server(server_config: &config::Serverconfig, cache: Arc<SnapshotCache>, program_config: Arc<ProgramConfig>, database: Option<Arc<SharedStorage>>, runtime: Handle) -> Result<Server> {
+    let (tx, rx) = mpsc::channel();
+    let server_config = server_config.clone();
+    thread::spawn(move || {
+        if let Err(e) = server_app(tx, server_config, cache, program_config, database, runtime) {
+            error!("Server stopped: {}", e);
+        }
+    });
+    // The sender is dropped without a message when the thread panics
+    let srv = rx.recv().map_err(|_| Error::Unavailable("Server".to_string()))??;
+    return Ok(srv);
+}
 
-    info!("Closing solend-apy-bot successfully");
+fn stop_server(srv: Option<Server>) {
+    if let Some(srv) = srv {
+        rt::System::new("").block_on(srv.stop(true));
+        info!("Server closed");
+    }
 }
 
-fn server_app(tx: mpsc::Sender<Server>) -> std::io::Result<()> {
+fn server_app(tx: mpsc::Sender<std::io::Result<Server>>, server_config: config::Serverconfig, cache: Arc<SnapshotCache>, program_config: Arc<ProgramConfig>, database: Option<Arc<SharedStorage>>, runtime: Handle) -> std::io::Result<()> {
     let mut sys = rt::System::new("server_system");
 
-    let config = config::Config::from_env().unwrap();
-    let url = format!("http://{}:{}", config.server.host, config.server.port);
+    let url = format!("http://{}:{}", server_config.host, server_config.port);
 
     let folder_name  = Path::new(env!("CARGO_MANIFEST_DIR")).join("web/dist/");
     // srv is server controller type, `dev::Server`
-    let srv = HttpServer::new(move || {
-        App::new()
+    let server = HttpServer::new(move || {
+        let app = App::new()
             .wrap(Logger::default())
+            .app_data(web::Data::from(cache.clone()))
+            .app_data(web::Data::from(program_config.clone()))
+            .app_data(web::Data::new(runtime.clone()));
+        // Without a database the chart data routes answer 503
+        let app = match &database {
+            Some(database) => app.app_data(web::Data::from(database.clone())),
+            None => app,
+        };
+        app.service(markets_route)
             .service(apy_route)
+            .service(market_apy_route)
             .service(apy_asset_route)
+            .service(market_apy_asset_route)
+            .service(apy_curve_route)
+            .service(market_apy_curve_route)
+            .service(apy_simulate_route)
+            .service(market_apy_simulate_route)
+            .service(reserves_route)
+            .service(market_reserves_route)
+            .service(reserve_asset_route)
+            .service(market_reserve_asset_route)
             .service(info_route)
+            .service(market_info_route)
             .service(chart_data)
+            .service(market_chart_data)
+            .service(history_route)
+            .service(market_history_route)
+            .service(export_route)
+            .service(market_export_route)
             .service(Files::new("/", folder_name.clone()).index_file("index.html"))
             //.service(Files::new("/", ).index_file("index.html"))
     })
-    .bind(format!("{}:{}", config.server.host, config.server.port))?
-    .run();
+    .bind(format!("{}:{}", server_config.host, server_config.port));
+    let srv = match server {
+        Ok(server) => server.run(),
+        Err(e) => {
+            let _ = tx.send(Err(e));
+            return Ok(());
+        },
+    };
 
     info!("Starting server at {}", url);
-    tx.send(srv.clone()).unwrap();
+    let _ = tx.send(Ok(srv.clone()));
     sys.block_on(srv)
 }
\ No newline at end of file
@@ -0,0 +1,195 @@
+use log::warn;
+use serde::Serialize;
+use spl_token_lending::math::{Decimal, TryDiv, TryMul, WAD};
+use spl_token_lending::state::Reserve;
+
+use crate::apy::{decimal_to_f64, rate_to_f64};
+use crate::curve::CurveParams;
+use crate::error::{AssetError, Error, Result};
+use crate::snapshot::Snapshot;
+use crate::utils::{AssetSymbol, Market, ProgramConfig};
+
+/// Amount of liquidity in tokens of the asset and in USD
+#[derive(Serialize, Debug, Clone)]
+pub struct LiquidityAmount {
+    pub amount: f64,
+    pub usd: f64,
+}
+
+/// Liquidity, caps and risk parameters of a reserve, the ratios and fees as fractions
+#[derive(Serialize, Debug, Clone)]
+pub struct ReserveInfo {
+    pub market: String,
+    pub asset: AssetSymbol,
+    pub name: String,
+    pub address: String,
+    pub mint_decimals: u8,
+    pub price: f64,
+    pub utilization: f64,
+    pub available: LiquidityAmount,
+    /// Supplied liquidity, the borrows included
+    pub deposits: LiquidityAmount,
+    /// Borrowed liquidity, including its accrued interest
+    pub borrows: LiquidityAmount,
+    /// Cap on the deposits in tokens, from the program config
+    pub deposit_cap: Option<f64>,
+    /// What can still be deposited before the cap is reached
+    pub deposit_cap_headroom: Option<LiquidityAmount>,
+    pub loan_to_value: f64,
+    pub liquidation_threshold: f64,
+    pub liquidation_bonus: f64,
+    pub borrow_fee: f64,
+    pub flash_loan_fee: f64,
+    /// Share of the borrow fee paid to the host of the transaction
+    pub host_fee: f64,
+    #[serde(flatten)]
+    pub curve: CurveParams,
+}
+
+impl ReserveInfo {
+    /// Describes `reserve` along with the reserve of the market lending the same mint
+    pub fn from_reserve(reserve: &Reserve, program_config: &ProgramConfig, market: &Market) -> Result<Self> {
+        let reserve_config = market.reserve_by_mint(&reserve.liquidity.mint_pubkey)?;
+        let asset_symbol = &reserve_config.asset;
+        let mint_address = reserve.liquidity.mint_pubkey.to_string();
+        let name = match program_config.asset_by_mint(&mint_address) {
+            Some(asset) => asset.name.clone(),
+            None => asset_symbol.to_string(),
+        };
+
+        let price = decimal_to_f64(reserve.liquidity.market_price)?;
+        let deposits = liquidity_amount(reserve, reserve.liquidity.total_supply()?)?;
+        let deposit_cap_headroom = reserve_config.user_supply_cap.map(|cap| {
+            let amount = (cap - deposits.amount).max(0f64);
+            LiquidityAmount { amount, usd: amount * price }
+        });
+
+        let config = &reserve.config;
+        return Ok(Self {
+            market: market.name.clone(),
+            asset: asset_symbol.clone(),
+            name,
+            address: reserve_config.address.clone(),
+            mint_decimals: reserve.liquidity.mint_decimals,
+            price,
+            utilization: rate_to_f64(reserve.liquidity.utilization_rate()?),
+            available: liquidity_amount(reserve, Decimal::from(reserve.liquidity.available_amount))?,
+            deposits,
+            borrows: liquidity_amount(reserve, reserve.liquidity.borrowed_amount_wads)?,
+            deposit_cap: reserve_config.user_supply_cap,
+            deposit_cap_headroom,
+            loan_to_value: config.loan_to_value_ratio as f64 / 100f64,
+            liquidation_threshold: config.liquidation_threshold as f64 / 100f64,
+            liquidation_bonus: config.liquidation_bonus as f64 / 100f64,
+            borrow_fee: config.fees.borrow_fee_wad as f64 / WAD as f64,
+            flash_loan_fee: config.fees.flash_loan_fee_wad as f64 / WAD as f64,
+            host_fee: config.fees.host_fee_percentage as f64 / 100f64,
+            curve: CurveParams::from_reserve(reserve),
+        });
+    }
+}
+
+/// Reserves of a market that could be read, along with the reason for those that could not
+#[derive(Serialize, Debug, Clone)]
+pub struct ReserveReport {
+    pub reserves: Vec<ReserveInfo>,
+    pub errors: Vec<AssetError>,
+}
+
+impl ReserveReport {
+    pub fn from_snapshot(snapshot: &Snapshot, program_config: &ProgramConfig, market: &Market) -> Self {
+        let mut report = Self { reserves: Vec::new(), errors: Vec::new() };
+        for reserve_config in market.reserves.iter() {
+            let asset_symbol = reserve_config.asset.clone();
+            let reserve = match snapshot.reserve(reserve_config) {
+                Ok(reserve) => reserve,
+                Err(_) => {
+                    report.errors.push(AssetError { error: snapshot.asset_error(&asset_symbol), asset: asset_symbol });
+                    continue;
+                },
+            };
+            match ReserveInfo::from_reserve(reserve, program_config, market) {
+                Ok(reserve_info) => report.reserves.push(reserve_info),
+                Err(e) => {
+                    warn!("Could not describe {} reserve: {}", asset_symbol, e);
+                    report.errors.push(AssetError::new(asset_symbol, &e));
+                },
+            }
+        }
+        return report;
+    }
+}
+
+/// `amount` of liquidity of `reserve`, in the smallest unit of its mint
+fn liquidity_amount(reserve: &Reserve, amount: Decimal) -> Result<LiquidityAmount> {
+    let mint_scale = 10u64.checked_pow(reserve.liquidity.mint_decimals as u32).ok_or(Error::Decode("Reserve mint decimals overflow".to_string()))?;
+    let tokens = amount.try_div(mint_scale)?;
+    return Ok(LiquidityAmount {
+        amount: decimal_to_f64(tokens)?,
+        usd: decimal_to_f64(tokens.try_mul(reserve.liquidity.market_price)?)?,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::testing::take_snapshot;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() <= 1e-9 * (1f64 + expected.abs()), "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn reserves_from_fixture() {
+        let (snapshot, program_config) = take_snapshot();
+        let market = program_config.market("main").unwrap();
+        let report = ReserveReport::from_snapshot(&snapshot, &program_config, market);
+        assert_eq!(report.reserves.len(), 2);
+        assert_eq!(report.errors.len(), 13);
+
+        // 2M SOL supplied at $180.5, 800k borrowed
+        let sol = &report.reserves[0];
+        assert_eq!(sol.asset.0, "SOL");
+        assert_eq!(sol.address, "8PbodeaosQP19SjYFx855UMqWxH2HynZLdBXmsrbac36");
+        assert_eq!(sol.mint_decimals, 9);
+        assert_close(sol.price, 180.5);
+        assert_close(sol.utilization, 0.4);
+        assert_close(sol.available.amount, 1_200_000f64);
+        assert_close(sol.available.usd, 1_200_000f64 * 180.5);
+        assert_close(sol.deposits.amount, 2_000_000f64);
+        assert_close(sol.borrows.amount, 800_000f64);
+        assert_close(sol.borrows.usd, 800_000f64 * 180.5);
+        assert!(sol.deposit_cap.is_none() && sol.deposit_cap_headroom.is_none());
+        assert_close(sol.loan_to_value, 0.75);
+        assert_close(sol.liquidation_threshold, 0.8);
+        assert_close(sol.liquidation_bonus, 0.05);
+        assert_close(sol.borrow_fee, 0.001);
+        assert_close(sol.flash_loan_fee, 0.003);
+        assert_close(sol.host_fee, 0.2);
+        assert_close(sol.curve.optimal_utilization, 0.8);
+        assert_close(sol.curve.min_borrow_rate, 0.0);
+        assert_close(sol.curve.optimal_borrow_rate, 0.08);
+        assert_close(sol.curve.max_borrow_rate, 1.5);
+
+        let usdc = &report.reserves[1];
+        assert_eq!(usdc.asset.0, "USDC");
+        assert_close(usdc.utilization, 0.6);
+        assert_close(usdc.deposits.usd, 75_000_000f64);
+    }
+
+    #[test]
+    fn deposit_cap_headroom() {
+        let (snapshot, program_config) = take_snapshot();
+        let mut market = program_config.market("main").unwrap().clone();
+        let sol = AssetSymbol("SOL".to_string());
+        let reserve = snapshot.reserve(market.reserve(&sol).unwrap()).unwrap();
+        for (cap, headroom) in [(2_100_000f64, 100_000f64), (2_000_000f64, 0f64), (1_500_000f64, 0f64)].iter() {
+            market.reserves.iter_mut().find(|reserve| reserve.asset == sol).unwrap().user_supply_cap = Some(*cap);
+            let info = ReserveInfo::from_reserve(reserve, &program_config, &market).unwrap();
+            assert_eq!(info.deposit_cap, Some(*cap));
+            let deposit_cap_headroom = info.deposit_cap_headroom.unwrap();
+            assert_close(deposit_cap_headroom.amount, *headroom);
+            assert_close(deposit_cap_headroom.usd, headroom * 180.5);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use log::{error, info};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::Duration,
+};
+
+use crate::db::DataType;
+use crate::error::{Error, Result};
+use crate::utils::ScheduleConfig;
+
+const DEFAULT_STATE_PATH: &str = "cache/schedule.json";
+
+/// What a single run of the bot does, either for a scheduled job or for the render and post commands
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Task {
+    pub data_type: Option<DataType>,
+    /// Tier derived from the one below, the old data points of every tier are pruned along
+    pub rollup: Option<DataType>,
+    pub screenshot: bool,
+    pub charts: bool,
+    pub twitter: bool,
+}
+
+pub struct Job {
+    pub name: String,
+    pub schedule: Schedule,
+    pub task: Task,
+}
+
+/// Runs the jobs one after the other in the current thread, so two runs never overlap. The last
+/// run of every job is kept in `state_path`, runs missed while the bot was down or busy are
+/// caught up once instead of being replayed one by one.
+pub struct Scheduler {
+    jobs: Vec<Job>,
+    last_runs: HashMap<String, DateTime<Utc>>,
+    state_path: PathBuf,
+}
+
+impl Scheduler {
+    /// Mirrors the jobs of the old crontab, each schedule can be overridden in the config. Only the
+    /// MINUTE data points are saved from snapshots, every other tier is rolled up by its own job
+    /// once its bucket is complete. Jobs due at once run in this order, finer tiers first.
+    pub fn from_config(config: &ScheduleConfig) -> Result<Self> {
+        let jobs = vec![
+            Self::job("minute", config.minute.as_deref().unwrap_or("0,30 * * * *"), Task { data_type: Some(DataType::MINUTE), screenshot: true, twitter: true, ..Task::default() })?,
+            Self::job("hour", config.hour.as_deref().unwrap_or("0 * * * *"), Task { rollup: Some(DataType::HOUR), ..Task::default() })?,
+            Self::job("day", config.day.as_deref().unwrap_or("0 0 * * *"), Task { rollup: Some(DataType::DAY), charts: true, twitter: true, ..Task::default() })?,
+            Self::job("week", config.week.as_deref().unwrap_or("0 0 * * Mon"), Task { rollup: Some(DataType::WEEK), ..Task::default() })?,
+        ];
+        let state_path = PathBuf::from(config.state.as_deref().unwrap_or(DEFAULT_STATE_PATH));
+        let last_runs = Self::read_state(&state_path);
+
+        return Ok(Self { jobs, last_runs, state_path });
+    }
+
+    fn job(name: &str, expression: &str, task: Task) -> Result<Job> {
+        // The cron crate expects a seconds field, plain crontab expressions run at second 0
+        let expression = if expression.split_whitespace().count() == 5 { format!("0 {}", expression) } else { expression.to_string() };
+        let schedule = Schedule::from_str(&expression)
+            .map_err(|e| Error::Config(format!("Invalid schedule '{}' for {} job: {}", expression, name, e)))?;
+        return Ok(Job { name: name.to_string(), schedule, task });
+    }
+
+    /// Runs the jobs as they become due until a message is received on `stop` or it disconnects
+    pub fn run<F: FnMut(&Task)>(&mut self, stop: &Receiver<()>, mut run_task: F) {
+        // Jobs that never ran start counting from now, there is nothing to catch up on
+        let now = Utc::now();
+        for job in self.jobs.iter() {
+            self.last_runs.entry(job.name.clone()).or_insert(now);
+        }
+
+        loop {
+            let now = Utc::now();
+            let mut next_run: Option<DateTime<Utc>> = None;
+            for job in self.jobs.iter() {
+                let last_run = self.last_runs[&job.name];
+                let due = match job.schedule.after(&last_run).next() {
+                    Some(due) => due,
+                    None => continue,
+                };
+                if due > now {
+                    next_run = Some(next_run.map_or(due, |next_run| next_run.min(due)));
+                    continue;
+                }
+
+                if now - due > chrono::Duration::minutes(1) {
+                    info!("Catching up on {} job missed at {}", job.name, due);
+                } else {
+                    info!("Running {} job", job.name);
+                }
+                run_task(&job.task);
+                self.last_runs.insert(job.name.clone(), now);
+                if let Err(e) = self.write_state() {
+                    error!("Could not save schedule state in {}: {}", self.state_path.display(), e);
+                }
+                // Time has passed while running, look at every job again
+                next_run = Some(Utc::now());
+            }
+
+            let timeout = match next_run {
+                Some(next_run) => (next_run - Utc::now()).to_std().unwrap_or(Duration::from_secs(0)),
+                None => {
+                    error!("No job is scheduled to run again");
+                    return;
+                },
+            };
+            match stop.recv_timeout(timeout) {
+                Err(RecvTimeoutError::Timeout) => continue,
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+
+    fn read_state(state_path: &Path) -> HashMap<String, DateTime<Utc>> {
+        let state = fs::read_to_string(state_path)
+            .map_err(Error::from)
+            .and_then(|body| serde_json::from_str(&body).map_err(Error::from));
+        match state {
+            Ok(state) => state,
+            Err(e) => {
+                info!("No schedule state loaded from {}: {}", state_path.display(), e);
+                HashMap::new()
+            },
+        }
+    }
+
+    fn write_state(&self) -> Result<()> {
+        fs::create_dir_all(self.state_path.parent().unwrap_or(Path::new("")))?;
+        fs::write(&self.state_path, serde_json::to_string_pretty(&self.last_runs)?)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn missed_runs_are_caught_up_once() {
+        let state_path = std::env::temp_dir().join(format!("solend-apy-bot-schedule-{}.json", std::process::id()));
+        let now = Utc::now();
+        // The hour job missed five runs and the day job three, the others never ran
+        let state: HashMap<&str, DateTime<Utc>> = vec![("hour", now - chrono::Duration::hours(5)), ("day", now - chrono::Duration::days(3))].into_iter().collect();
+        fs::write(&state_path, serde_json::to_string(&state).unwrap()).unwrap();
+
+        let config = ScheduleConfig { state: Some(state_path.to_string_lossy().to_string()), ..ScheduleConfig::default() };
+        let mut scheduler = Scheduler::from_config(&config).unwrap();
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let stop = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(500));
+            stop_tx.send(()).unwrap();
+        });
+        let mut runs = Vec::new();
+        scheduler.run(&stop_rx, |task| runs.push(task.rollup));
+        stop.join().unwrap();
+
+        // Finer tiers first
+        assert_eq!(runs, vec![Some(DataType::HOUR), Some(DataType::DAY)]);
+        let state: HashMap<String, DateTime<Utc>> = serde_json::from_str(&fs::read_to_string(&state_path).unwrap()).unwrap();
+        fs::remove_file(&state_path).unwrap();
+        assert!(state["hour"] >= now);
+        assert!(state["day"] >= now);
+        assert!(state["week"] >= now);
+    }
+}
@@ -0,0 +1,286 @@
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::Serialize;
+use spl_token_lending::state::Reserve;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::{runtime::Handle, task::JoinHandle};
+
+use crate::apy::{APY, APYReport};
+use crate::error::{Error, Result};
+use crate::sources::SharedSource;
+use crate::stats::Stats;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::utils::{self, AssetSymbol, Market, ProgramConfig};
+
+/// APYs and stats of a market calculated from a single read of its reserves
+#[derive(Serialize, Debug, Clone)]
+pub struct Snapshot {
+    pub market: String,
+    pub slot: u64,
+    pub last_updated: DateTime<Utc>,
+    pub apys: APYReport,
+    pub stats: Stats,
+    /// Rewards or prices that could not be fetched, the APYs and stats are calculated without them
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// Reserves read, by the mint of their liquidity, and rewards fetched for the APYs, to evaluate
+    /// them at other utilizations
+    #[serde(skip)]
+    pub reserves: HashMap<Pubkey, Reserve>,
+    #[serde(skip)]
+    pub reward_stats: serde_json::Value,
+    #[serde(skip)]
+    pub external_reward_stats: serde_json::Value,
+}
+
+impl Snapshot {
+    pub async fn from_source(source: &SharedSource, program_config: &ProgramConfig, market: &Market) -> Result<Self> {
+        let assets = market.assets();
+        let account_pks = market.reserve_pubkeys(&assets)?;
+
+        let (slot, reserves, reward_stats, external_reward_stats, slnd_price, mnde_price) = futures::join!(
+            source.get_slot(),
+            source.get_reserves(&account_pks),
+            source.get_reward_stats(),
+            source.get_external_reward_stats(),
+            source.get_slnd_price(),
+            source.get_mnde_price()
+        );
+        let (slot, reserves) = (slot?, reserves?);
+
+        // The rates only depend on the reserves, the snapshot is kept without the rewards
+        // or prices that are missing
+        let mut warnings = Vec::new();
+        let reward_stats = or_warn(reward_stats, "Reward stats", serde_json::Value::Null, &mut warnings);
+        let external_reward_stats = or_warn(external_reward_stats, "External reward stats", serde_json::Value::Null, &mut warnings);
+        let slnd_price = or_warn(slnd_price, "SLND price", 0f64, &mut warnings);
+        let mnde_price = or_warn(mnde_price, "MNDE price", 0f64, &mut warnings);
+
+        let apys = APY::from_reserves(&reserves, program_config, market, &assets, &reward_stats, &external_reward_stats, slnd_price, mnde_price);
+        let stats = Stats::from_reserves(&reserves, market, &assets, slnd_price, mnde_price)?;
+        let read_reserves = reserves.iter()
+            .filter_map(|reserve| reserve.as_ref().ok().map(|reserve| (reserve.liquidity.mint_pubkey, reserve.clone())))
+            .collect();
+
+        return Ok(Self {
+            market: market.name.clone(),
+            slot,
+            last_updated: Utc::now(),
+            apys,
+            stats,
+            warnings,
+            reserves: read_reserves,
+            reward_stats,
+            external_reward_stats,
+        });
+    }
+
+    /// Reserve of `reserve_config` as read, found by its mint
+    pub fn reserve(&self, reserve_config: &utils::Reserve) -> Result<&Reserve> {
+        let asset_symbol = &reserve_config.asset;
+        return self.reserves.get(&reserve_config.mint()?)
+            .ok_or_else(|| Error::Unavailable(format!("{} reserve in {} market ({})", asset_symbol, self.market, self.asset_error(asset_symbol))));
+    }
+
+    /// Why the reserve of `asset_symbol` could not be read or its APY calculated
+    pub fn asset_error(&self, asset_symbol: &AssetSymbol) -> String {
+        return self.apys.errors.iter()
+            .find(|e| e.asset == *asset_symbol)
+            .map(|e| e.error.clone())
+            .unwrap_or_default();
+    }
+}
+
+/// Data served by a route along with the snapshot it comes from
+#[derive(Serialize)]
+pub struct Cached<'a, T: Serialize> {
+    #[serde(flatten)]
+    pub data: &'a T,
+    pub slot: u64,
+    pub last_updated: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+impl<'a, T: Serialize> Cached<'a, T> {
+    pub fn new(data: &'a T, snapshot: &Snapshot) -> Self {
+        return Self { data, slot: snapshot.slot, last_updated: snapshot.last_updated, warnings: snapshot.warnings.clone() };
+    }
+}
+
+/// Value of `result`, or `default` when `what` could not be fetched
+fn or_warn<T>(result: Result<T>, what: &str, default: T, warnings: &mut Vec<String>) -> T {
+    return match result {
+        Ok(value) => value,
+        Err(e) => {
+            warn!("{} unavailable, calculating without it: {}", what, e);
+            warnings.push(format!("{} unavailable: {}", what, e));
+            default
+        },
+    };
+}
+
+/// Latest snapshot of every market, kept fresh by a background poller so the routes do not have
+/// to hit the RPC nodes and APIs on every request
+pub struct SnapshotCache {
+    source: Arc<SharedSource>,
+    program_config: Arc<ProgramConfig>,
+    snapshots: RwLock<HashMap<String, Arc<Snapshot>>>,
+}
+
+impl SnapshotCache {
+    pub fn new(source: Arc<SharedSource>, program_config: Arc<ProgramConfig>) -> Self {
+        return Self { source, program_config, snapshots: RwLock::new(HashMap::new()) };
+    }
+
+    /// Returns the cached snapshot of `market`, taking it on the spot if the poller has not yet
+    pub async fn get(&self, market: &Market) -> Result<Arc<Snapshot>> {
+        let snapshot = self.snapshots.read().unwrap().get(&market.name).cloned();
+        if let Some(snapshot) = snapshot {
+            return Ok(snapshot);
+        }
+        return self.refresh(market).await;
+    }
+
+    /// Takes a new snapshot of `market`, the previous one is kept when it fails
+    pub async fn refresh(&self, market: &Market) -> Result<Arc<Snapshot>> {
+        let snapshot = Arc::new(Snapshot::from_source(self.source.as_ref(), &self.program_config, market).await?);
+        info!("Snapshot of {} market taken at slot {}", market.name, snapshot.slot);
+        self.snapshots.write().unwrap().insert(market.name.clone(), snapshot.clone());
+        return Ok(snapshot);
+    }
+
+    /// Refreshes every market of the program config each `interval` in the background
+    pub fn spawn_poller(cache: Arc<Self>, runtime: &Handle, interval: Duration) -> JoinHandle<()> {
+        return runtime.spawn(async move {
+            loop {
+                for market in cache.program_config.markets.iter() {
+                    if let Err(e) = cache.refresh(market).await {
+                        error!("Could not refresh snapshot of {} market: {}", market.name, e);
+                    }
+                }
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
+}
+
+/// Snapshot of the recorded fixture shared by the tests of the modules built on top of it
+#[cfg(test)]
+pub mod testing {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::sources::FixtureSource;
+    use crate::utils::Cluster;
+
+    /// Main market with only the SOL and USDC reserves recorded, at round amounts and prices
+    pub fn fixture() -> (FixtureSource, ProgramConfig) {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/main");
+        let program_config = ProgramConfig::from_json(Cluster::MAINNET.embedded_config().unwrap()).unwrap();
+        return (FixtureSource::new(dir), program_config);
+    }
+
+    pub fn take_snapshot() -> (Snapshot, ProgramConfig) {
+        let (source, program_config) = fixture();
+        let market = program_config.market("main").unwrap().clone();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let snapshot = runtime.block_on(Snapshot::from_source(&source, &program_config, &market)).unwrap();
+        return (snapshot, program_config);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{fixture, take_snapshot};
+    use super::*;
+    use crate::apy::{compound_f64, BORROW_FEE_HOLDING_DAYS};
+    use crate::sources::FixtureSource;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() <= 1e-9 * (1f64 + expected.abs()), "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn snapshot_from_fixture() {
+        let (snapshot, program_config) = take_snapshot();
+        let market = program_config.market("main").unwrap();
+        assert_eq!(snapshot.market, "main");
+        assert_eq!(snapshot.slot, 110_000_000);
+        assert!(snapshot.warnings.is_empty());
+
+        let assets: Vec<&str> = snapshot.apys.apys.iter().map(|apy| apy.asset.0.as_str()).collect();
+        assert_eq!(assets, vec!["SOL", "USDC"]);
+        assert_eq!(snapshot.reserves.len(), 2);
+
+        // SOL: 40% utilization, 4% borrow APR, SLND rewards of 10% on both sides
+        let sol = &snapshot.apys.apys[0];
+        assert_close(sol.price, 180.5);
+        assert_close(sol.utilization.unwrap(), 0.4);
+        assert_close(sol.supply_rewards, 0.1);
+        assert_close(sol.borrow_rewards, 0.1);
+        assert_eq!(sol.weight_supply, "SLND");
+        assert_eq!(sol.mnde_supply_rewards, None);
+        assert_close(sol.supply_apr.unwrap(), 0.016 + 0.1);
+        assert_close(sol.supply_gross.unwrap(), compound_f64(0.4 * (0.04 + 0.001 * 365f64 / BORROW_FEE_HOLDING_DAYS as f64)) + 0.1);
+        assert_close(sol.borrow_apr.unwrap(), 0.04 - 0.1);
+        assert_close(sol.supply, compound_f64(0.016) + 0.1);
+        assert_close(sol.borrow, compound_f64(0.04) - 0.1);
+
+        // USDC: 60% utilization, 6% borrow APR, SLND rewards of 5% on supply and 10% on borrows
+        let usdc = &snapshot.apys.apys[1];
+        assert_close(usdc.price, 1.0);
+        assert_close(usdc.utilization.unwrap(), 0.6);
+        assert_close(usdc.supply_rewards, 0.05);
+        assert_close(usdc.borrow_rewards, 0.1);
+        assert_close(usdc.supply_apr.unwrap(), 0.036 + 0.05);
+        assert_close(usdc.supply_gross.unwrap(), compound_f64(0.6 * (0.06 + 0.0005 * 365f64 / BORROW_FEE_HOLDING_DAYS as f64)) + 0.05);
+        assert_close(usdc.borrow_apr.unwrap(), 0.06 - 0.1);
+        assert_close(usdc.supply, compound_f64(0.036) + 0.05);
+        assert_close(usdc.borrow, compound_f64(0.06) - 0.1);
+
+        // Every other reserve of the market is missing from the fixture
+        assert_eq!(snapshot.apys.errors.len(), 13);
+        for error in snapshot.apys.errors.iter() {
+            assert!(error.error.ends_with("not found"), "{}: {}", error.asset, error.error);
+            assert!(snapshot.reserve(market.reserve(&error.asset).unwrap()).is_err());
+        }
+
+        // 2M SOL at $180.5 and 75M USDC supplied, 800k SOL and 45M USDC borrowed
+        assert_close(snapshot.stats.total_supplied, 361_000_000f64 + 75_000_000f64);
+        assert_close(snapshot.stats.total_borrowed, 144_400_000f64 + 45_000_000f64);
+        assert_close(snapshot.stats.slnd_price, 2.0);
+        assert_close(snapshot.stats.mnde_price, 0.4);
+    }
+
+    #[test]
+    fn snapshot_without_rewards() {
+        let dir = std::env::temp_dir().join(format!("solend-apy-bot-fixture-{}", std::process::id()));
+        let (source, program_config) = fixture();
+        std::fs::create_dir_all(dir.join("reserves")).unwrap();
+        for entry in std::fs::read_dir(source.dir().join("reserves")).unwrap() {
+            let path = entry.unwrap().path();
+            std::fs::copy(&path, dir.join("reserves").join(path.file_name().unwrap())).unwrap();
+        }
+        std::fs::copy(source.dir().join("slot.json"), dir.join("slot.json")).unwrap();
+
+        // Rewards and prices are missing, the rates are still calculated
+        let source = FixtureSource::new(dir.clone());
+        let market = program_config.market("main").unwrap().clone();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let snapshot = runtime.block_on(Snapshot::from_source(&source, &program_config, &market)).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(snapshot.warnings.len(), 4);
+        assert_eq!(snapshot.apys.apys.len(), 2);
+        assert_close(snapshot.apys.apys[0].supply_rewards, 0.0);
+        assert_close(snapshot.apys.apys[0].borrow, compound_f64(0.04));
+        assert_close(snapshot.stats.slnd_price, 0.0);
+        assert_close(snapshot.stats.total_supplied, 361_000_000f64 + 75_000_000f64);
+    }
+}
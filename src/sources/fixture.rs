@@ -0,0 +1,137 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{program_pack::Pack, pubkey::Pubkey};
+use spl_token_lending::state::Reserve;
+use std::{fs, path::PathBuf};
+
+use crate::error::{Error, Result};
+use crate::sources::{MarketSource, PriceSource, ReserveSource, RewardSource};
+
+const RESERVES_DIR: &str = "reserves";
+const REWARD_STATS_FILE: &str = "reward-stats.json";
+const EXTERNAL_REWARD_STATS_FILE: &str = "external-reward-stats.json";
+const PRICES_FILE: &str = "prices.json";
+const SLOT_FILE: &str = "slot.json";
+const DATE_FILE: &str = "date.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FixturePrices {
+    pub slnd: f64,
+    pub mnde: f64,
+}
+
+/// Reads a market snapshot previously stored on disk, laid out as:
+///
+/// ```text
+/// <dir>/reserves/<reserve pubkey>.bin   raw reserve account data
+/// <dir>/reward-stats.json               api.solend.fi reward stats
+/// <dir>/external-reward-stats.json      api.solend.fi external reward stats
+/// <dir>/prices.json                     { "slnd": f64, "mnde": f64 }
+/// <dir>/slot.json                       slot the fixture was recorded at
+/// <dir>/date.json                       date the fixture was recorded at
+/// ```
+pub struct FixtureSource {
+    dir: PathBuf,
+}
+
+impl FixtureSource {
+    pub fn new(dir: PathBuf) -> Self {
+        return Self { dir };
+    }
+
+    /// Stores the current state of `source` for the given reserves so it can be replayed later
+    pub async fn record(source: &dyn MarketSource, reserve_pks: &[Pubkey], dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(dir.join(RESERVES_DIR))?;
+
+        let slot = source.get_slot().await?;
+        fs::write(dir.join(SLOT_FILE), serde_json::to_string(&slot)?)?;
+        fs::write(dir.join(DATE_FILE), serde_json::to_string(&Utc::now())?)?;
+
+        let reserves = source.get_reserves(reserve_pks).await?;
+        for (reserve_pk, reserve) in reserve_pks.iter().zip(reserves) {
+            let mut data = vec![0u8; Reserve::LEN];
+            reserve?.pack_into_slice(&mut data);
+            fs::write(dir.join(RESERVES_DIR).join(format!("{}.bin", reserve_pk)), data)?;
+        }
+
+        let reward_stats = source.get_reward_stats().await?;
+        fs::write(dir.join(REWARD_STATS_FILE), serde_json::to_string_pretty(&reward_stats)?)?;
+        let external_reward_stats = source.get_external_reward_stats().await?;
+        fs::write(dir.join(EXTERNAL_REWARD_STATS_FILE), serde_json::to_string_pretty(&external_reward_stats)?)?;
+
+        let prices = FixturePrices { slnd: source.get_slnd_price().await?, mnde: source.get_mnde_price().await? };
+        fs::write(dir.join(PRICES_FILE), serde_json::to_string_pretty(&prices)?)?;
+
+        info!("Fixture recorded in {}", dir.display());
+        return Ok(Self::new(dir));
+    }
+
+    pub fn dir(&self) -> &PathBuf {
+        return &self.dir;
+    }
+
+    /// Date the fixture was recorded at, unknown for fixtures recorded before it was stored
+    pub fn recorded_at(&self) -> Result<Option<DateTime<Utc>>> {
+        if !self.dir.join(DATE_FILE).exists() {
+            return Ok(None);
+        }
+        return Ok(Some(serde_json::from_value(self.read_json(DATE_FILE)?)?));
+    }
+
+    fn read_json(&self, file_name: &str) -> Result<serde_json::Value> {
+        let body = fs::read_to_string(self.dir.join(file_name))?;
+        return Ok(serde_json::from_str(&body)?);
+    }
+
+    fn read_prices(&self) -> Result<FixturePrices> {
+        return Ok(serde_json::from_value(self.read_json(PRICES_FILE)?)?);
+    }
+
+    fn read_reserve(&self, reserve_pk: &Pubkey) -> Result<Reserve> {
+        let path = self.dir.join(RESERVES_DIR).join(format!("{}.bin", reserve_pk));
+        if !path.exists() {
+            return Err(Error::NotFound(format!("Reserve fixture {}", path.display())));
+        }
+        let data = fs::read(path)?;
+        return Ok(Reserve::unpack_from_slice(&data)?);
+    }
+}
+
+#[async_trait]
+impl ReserveSource for FixtureSource {
+    async fn get_reserves(&self, reserve_pks: &[Pubkey]) -> Result<Vec<Result<Reserve>>> {
+        return Ok(reserve_pks.iter().map(|reserve_pk| self.read_reserve(reserve_pk)).collect());
+    }
+
+    async fn get_slot(&self) -> Result<u64> {
+        // Fixtures recorded before the slot was stored report slot 0
+        if !self.dir.join(SLOT_FILE).exists() {
+            return Ok(0);
+        }
+        return Ok(serde_json::from_value(self.read_json(SLOT_FILE)?)?);
+    }
+}
+
+#[async_trait]
+impl RewardSource for FixtureSource {
+    async fn get_reward_stats(&self) -> Result<serde_json::Value> {
+        return self.read_json(REWARD_STATS_FILE);
+    }
+
+    async fn get_external_reward_stats(&self) -> Result<serde_json::Value> {
+        return self.read_json(EXTERNAL_REWARD_STATS_FILE);
+    }
+}
+
+#[async_trait]
+impl PriceSource for FixtureSource {
+    async fn get_slnd_price(&self) -> Result<f64> {
+        return Ok(self.read_prices()?.slnd);
+    }
+
+    async fn get_mnde_price(&self) -> Result<f64> {
+        return Ok(self.read_prices()?.mnde);
+    }
+}
@@ -0,0 +1,43 @@
+pub mod fixture;
+pub mod rpc;
+pub mod rpc_client;
+
+pub use self::fixture::*;
+pub use self::rpc::*;
+pub use self::rpc_client::*;
+
+use async_trait::async_trait;
+use solana_sdk::pubkey::Pubkey;
+use spl_token_lending::state::Reserve;
+
+use crate::error::Result;
+
+/// Provides the on-chain state of the lending reserves. The outer result fails when the source
+/// cannot be read at all, the inner ones when a single reserve is missing or cannot be decoded.
+#[async_trait]
+pub trait ReserveSource {
+    async fn get_reserves(&self, reserve_pks: &[Pubkey]) -> Result<Vec<Result<Reserve>>>;
+    /// Slot the reserves are read at
+    async fn get_slot(&self) -> Result<u64>;
+}
+
+/// Provides the liquidity mining stats published by api.solend.fi
+#[async_trait]
+pub trait RewardSource {
+    async fn get_reward_stats(&self) -> Result<serde_json::Value>;
+    async fn get_external_reward_stats(&self) -> Result<serde_json::Value>;
+}
+
+/// Provides the USD price of the reward tokens
+#[async_trait]
+pub trait PriceSource {
+    async fn get_slnd_price(&self) -> Result<f64>;
+    async fn get_mnde_price(&self) -> Result<f64>;
+}
+
+/// Everything needed to calculate the APYs and stats of a market
+pub trait MarketSource: ReserveSource + RewardSource + PriceSource {}
+
+impl<T: ReserveSource + RewardSource + PriceSource> MarketSource for T {}
+
+pub type SharedSource = dyn MarketSource + Send + Sync;
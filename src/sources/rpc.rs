@@ -0,0 +1,260 @@
+use async_trait::async_trait;
+use log::{info, warn};
+use solana_sdk::{account_info::IntoAccountInfo, program_pack::Pack, pubkey::Pubkey};
+use spl_token_lending::state::Reserve;
+use std::{
+    future::Future,
+    str::FromStr,
+    sync::{atomic::{AtomicUsize, Ordering}, Mutex},
+    time::{Duration, Instant},
+};
+use switchboard_program::{self, AggregatorState, RoundResult};
+
+use crate::error::{Error, Result};
+use crate::sources::{PriceSource, ReserveSource, RewardSource, RpcClient};
+
+const SLND_FEED_ACCOUNT: &str = "7QKyBR3zLRhoEH5UMjcG8emDD2J2CCDmkxv3qsa2Mqif";
+const REWARD_STATS_URL: &str = "https://api.solend.fi/liquidity-mining/reward-stats";
+const EXTERNAL_REWARD_STATS_URL: &str = "https://api.solend.fi/liquidity-mining/external-reward-stats";
+const MNDE_PRICE_URL: &str = "https://api.coingecko.com/api/v3/simple/price?ids=marinade&vs_currencies=usd";
+
+/// Time an endpoint that failed is left out of rotation before it is checked again
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A list of RPC endpoints used in order of preference. Calls go to the most preferred endpoint in
+/// rotation and fail over to the next ones when it errors, so a single flaky provider does not
+/// break a run. An endpoint that errors is out of rotation until it passes a health check, run
+/// once `HEALTH_CHECK_INTERVAL` has passed, so the healthy endpoints get no extra requests.
+pub struct RpcPool {
+    endpoints: Vec<RpcClient>,
+    /// When every endpoint last failed, none while it is in rotation
+    failed_at: Vec<Mutex<Option<Instant>>>,
+    current: AtomicUsize,
+    health_check_interval: Duration,
+}
+
+impl RpcPool {
+    pub fn new(rpc_urls: &[String], timeout: Duration) -> Result<Self> {
+        let endpoints = rpc_urls.iter()
+            .map(|url| RpcClient::new_with_timeout(url.clone(), timeout))
+            .collect::<Result<Vec<RpcClient>>>()?;
+        let failed_at = endpoints.iter().map(|_| Mutex::new(None)).collect();
+        return Ok(Self { endpoints, failed_at, current: AtomicUsize::new(0), health_check_interval: HEALTH_CHECK_INTERVAL });
+    }
+
+    /// Runs `f` against the endpoints in rotation until one succeeds. When they all fail, those
+    /// out of rotation are tried as a last resort.
+    pub async fn call<T, F, Fut>(&self, f: F) -> Result<T> where F: Fn(RpcClient) -> Fut, Fut: Future<Output = Result<T>> {
+        let mut out_of_rotation = Vec::new();
+        let mut last_error = None;
+        for index in 0..self.endpoints.len() {
+            if !self.in_rotation(index).await {
+                out_of_rotation.push(index);
+                continue;
+            }
+            match self.call_endpoint(index, &f).await {
+                Ok(result) => return Ok(result),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        for index in out_of_rotation {
+            match self.call_endpoint(index, &f).await {
+                Ok(result) => return Ok(result),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        return Err(last_error.unwrap_or(Error::Config("No RPC endpoint configured".to_string())));
+    }
+
+    /// Whether the endpoint has not failed, or failed long enough ago and passes a health check
+    async fn in_rotation(&self, index: usize) -> bool {
+        let failed_at = *self.failed_at[index].lock().unwrap();
+        match failed_at {
+            None => return true,
+            Some(failed_at) if failed_at.elapsed() < self.health_check_interval => return false,
+            Some(_) => {},
+        }
+        let rpc_client = &self.endpoints[index];
+        return match rpc_client.get_health().await {
+            Ok(_) => {
+                info!("RPC endpoint {} is healthy again", rpc_client.url());
+                *self.failed_at[index].lock().unwrap() = None;
+                true
+            },
+            Err(e) => {
+                warn!("RPC endpoint {} is still unhealthy: {}", rpc_client.url(), e);
+                *self.failed_at[index].lock().unwrap() = Some(Instant::now());
+                false
+            },
+        };
+    }
+
+    async fn call_endpoint<T, F, Fut>(&self, index: usize, f: &F) -> Result<T> where F: Fn(RpcClient) -> Fut, Fut: Future<Output = Result<T>> {
+        let rpc_client = &self.endpoints[index];
+        match f(rpc_client.clone()).await {
+            Ok(result) => {
+                *self.failed_at[index].lock().unwrap() = None;
+                if self.current.swap(index, Ordering::SeqCst) != index {
+                    info!("Using RPC endpoint {}", rpc_client.url());
+                }
+                return Ok(result);
+            },
+            Err(e) => {
+                warn!("RPC call to {} failed: {}", rpc_client.url(), e);
+                *self.failed_at[index].lock().unwrap() = Some(Instant::now());
+                return Err(e);
+            },
+        }
+    }
+}
+
+/// Reads the reserves and the SLND feed from Solana RPC nodes, and the rewards and MNDE price from
+/// the Solend and CoinGecko APIs
+pub struct RpcSource {
+    rpc_pool: RpcPool,
+    http_client: reqwest::Client,
+}
+
+impl RpcSource {
+    pub fn new(rpc_urls: &[String], timeout: Duration) -> Result<Self> {
+        let http_client = reqwest::Client::builder().timeout(timeout).build()?;
+        return Ok(Self { rpc_pool: RpcPool::new(rpc_urls, timeout)?, http_client });
+    }
+
+    async fn get_json(&self, url: &str) -> Result<serde_json::Value> {
+        return Ok(self.http_client.get(url).send().await?.error_for_status()?.json().await?);
+    }
+}
+
+#[async_trait]
+impl ReserveSource for RpcSource {
+    async fn get_reserves(&self, reserve_pks: &[Pubkey]) -> Result<Vec<Result<Reserve>>> {
+        let accounts = self.rpc_pool.call(|rpc_client| async move { rpc_client.get_multiple_accounts(reserve_pks).await }).await?;
+        let mut result = Vec::<Result<Reserve>>::new();
+        for (reserve_pk, account) in reserve_pks.iter().zip(accounts.iter()) {
+            let reserve = match account {
+                Some(account) => Reserve::unpack_from_slice(&account.data).map_err(Error::from),
+                None => Err(Error::NotFound(format!("Reserve account {}", reserve_pk))),
+            };
+            result.push(reserve);
+        }
+        return Ok(result);
+    }
+
+    async fn get_slot(&self) -> Result<u64> {
+        return self.rpc_pool.call(|rpc_client| async move { rpc_client.get_slot().await }).await;
+    }
+}
+
+#[async_trait]
+impl RewardSource for RpcSource {
+    async fn get_reward_stats(&self) -> Result<serde_json::Value> {
+        return self.get_json(REWARD_STATS_URL).await;
+    }
+
+    async fn get_external_reward_stats(&self) -> Result<serde_json::Value> {
+        return self.get_json(EXTERNAL_REWARD_STATS_URL).await;
+    }
+}
+
+#[async_trait]
+impl PriceSource for RpcSource {
+    async fn get_slnd_price(&self) -> Result<f64> {
+        info!("Get slnd price");
+        let slnd_feed_data_pk = Pubkey::from_str(SLND_FEED_ACCOUNT).map_err(|e| Error::Config(e.to_string()))?;
+        let slnd_feed_data_account = self.rpc_pool.call(|rpc_client| async move { rpc_client.get_account(&slnd_feed_data_pk).await }).await?;
+        let mut account = (slnd_feed_data_pk, slnd_feed_data_account);
+        let slnd_feed_acc_info = account.into_account_info();
+        let aggregator: AggregatorState = switchboard_program::get_aggregator(&slnd_feed_acc_info)?;
+        let round_result: RoundResult = switchboard_program::get_aggregator_result(&aggregator)?;
+        let price = round_result.result.unwrap_or(0f64);
+        return Ok(price);
+    }
+
+    async fn get_mnde_price(&self) -> Result<f64> {
+        info!("Get mnde price");
+        let v = self.get_json(MNDE_PRICE_URL).await?;
+        let price = &v["marinade"]["usd"];
+        return Ok(price.as_f64().unwrap_or(0f64));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::sync::{atomic::AtomicBool, Arc};
+
+    use super::*;
+    use crate::sources::testing::{result, StubRpc};
+
+    fn pool(stubs: &[&StubRpc]) -> RpcPool {
+        let urls: Vec<String> = stubs.iter().map(|stub| stub.url.clone()).collect();
+        return RpcPool::new(&urls, Duration::from_secs(5)).unwrap();
+    }
+
+    fn get_slot(pool: &RpcPool, runtime: &tokio::runtime::Runtime) -> Result<u64> {
+        return runtime.block_on(pool.call(|rpc_client| async move { rpc_client.get_slot().await }));
+    }
+
+    /// Endpoint answering `slot` once `up` is set, healthy whenever it is up
+    fn endpoint(slot: u64, up: &Arc<AtomicBool>) -> StubRpc {
+        let up = up.clone();
+        return StubRpc::start(move |method| match (method, up.load(Ordering::SeqCst)) {
+            ("getSlot", true) => result(json!(slot)),
+            ("getHealth", true) => result(json!("ok")),
+            _ => None,
+        });
+    }
+
+    #[test]
+    fn fails_over_without_health_checks() {
+        let (primary_up, backup_up) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicBool::new(true)));
+        let (primary, backup) = (endpoint(1, &primary_up), endpoint(2, &backup_up));
+        let pool = pool(&[&primary, &backup]);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        assert_eq!(get_slot(&pool, &runtime).unwrap(), 2);
+        // The primary is out of rotation, it is not called again before its health check is due
+        primary_up.store(true, Ordering::SeqCst);
+        assert_eq!(get_slot(&pool, &runtime).unwrap(), 2);
+        assert_eq!(primary.calls("getSlot"), 1);
+        assert_eq!(backup.calls("getSlot"), 2);
+        assert_eq!(primary.calls("getHealth") + backup.calls("getHealth"), 0);
+    }
+
+    #[test]
+    fn back_to_the_primary_once_healthy() {
+        let (primary_up, backup_up) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicBool::new(true)));
+        let (primary, backup) = (endpoint(1, &primary_up), endpoint(2, &backup_up));
+        let mut pool = pool(&[&primary, &backup]);
+        pool.health_check_interval = Duration::from_millis(0);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        assert_eq!(get_slot(&pool, &runtime).unwrap(), 2);
+        // Still down at its health check
+        assert_eq!(get_slot(&pool, &runtime).unwrap(), 2);
+        assert_eq!(primary.calls("getHealth"), 1);
+        assert_eq!(primary.calls("getSlot"), 1);
+
+        primary_up.store(true, Ordering::SeqCst);
+        assert_eq!(get_slot(&pool, &runtime).unwrap(), 1);
+        assert_eq!(get_slot(&pool, &runtime).unwrap(), 1);
+        assert_eq!(primary.calls("getHealth"), 2);
+        assert_eq!(backup.calls("getHealth"), 0);
+    }
+
+    #[test]
+    fn out_of_rotation_as_last_resort() {
+        let (primary_up, backup_up) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicBool::new(false)));
+        let (primary, backup) = (endpoint(1, &primary_up), endpoint(2, &backup_up));
+        let pool = pool(&[&primary, &backup]);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        assert!(get_slot(&pool, &runtime).is_err());
+        // Both are out of rotation, they are still tried
+        backup_up.store(true, Ordering::SeqCst);
+        assert_eq!(get_slot(&pool, &runtime).unwrap(), 2);
+        assert_eq!(primary.calls("getSlot"), 2);
+        assert_eq!(primary.calls("getHealth") + backup.calls("getHealth"), 0);
+    }
+}
@@ -0,0 +1,249 @@
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use std::{str::FromStr, time::Duration};
+
+use crate::error::{Error, Result};
+
+/// Async client for the few Solana JSON RPC methods the bot needs, built on the same reqwest
+/// client as the rest of the data layer
+#[derive(Clone)]
+pub struct RpcClient {
+    url: String,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<RpcResponseError>,
+}
+
+#[derive(Deserialize)]
+struct RpcResponseError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct RpcContextValue<T> {
+    value: T,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcAccount {
+    lamports: u64,
+    /// Encoded data and its encoding, always base64 in the requests below
+    data: (String, String),
+    owner: String,
+    executable: bool,
+    rent_epoch: u64,
+}
+
+impl RpcAccount {
+    fn into_account(self) -> Result<Account> {
+        let data = base64::decode(&self.data.0).map_err(|e| Error::Decode(format!("Invalid account data: {}", e)))?;
+        let owner = Pubkey::from_str(&self.owner).map_err(|e| Error::Decode(format!("Invalid account owner '{}': {}", self.owner, e)))?;
+        return Ok(Account {
+            lamports: self.lamports,
+            data,
+            owner,
+            executable: self.executable,
+            rent_epoch: self.rent_epoch,
+        });
+    }
+}
+
+impl RpcClient {
+    pub fn new_with_timeout(url: String, timeout: Duration) -> Result<Self> {
+        let client = reqwest::Client::builder().timeout(timeout).build()?;
+        return Ok(Self { url, client });
+    }
+
+    pub fn url(&self) -> &str {
+        return &self.url;
+    }
+
+    async fn send<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T> {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let response: RpcResponse<T> = self.client.post(&self.url).json(&request).send().await?.error_for_status()?.json().await?;
+        if let Some(error) = response.error {
+            return Err(Error::Rpc(format!("{} failed with code {}: {}", method, error.code, error.message)));
+        }
+        return response.result.ok_or(Error::Rpc(format!("{} returned no result", method)));
+    }
+
+    pub async fn get_health(&self) -> Result<()> {
+        let _: String = self.send("getHealth", json!([])).await?;
+        return Ok(());
+    }
+
+    pub async fn get_slot(&self) -> Result<u64> {
+        return self.send("getSlot", json!([])).await;
+    }
+
+    pub async fn get_account(&self, pubkey: &Pubkey) -> Result<Account> {
+        let params = json!([pubkey.to_string(), { "encoding": "base64" }]);
+        let response: RpcContextValue<Option<RpcAccount>> = self.send("getAccountInfo", params).await?;
+        match response.value {
+            Some(account) => account.into_account(),
+            None => Err(Error::NotFound(format!("Account {}", pubkey))),
+        }
+    }
+
+    pub async fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> Result<Vec<Option<Account>>> {
+        let pubkeys: Vec<String> = pubkeys.iter().map(|pubkey| pubkey.to_string()).collect();
+        let params = json!([pubkeys, { "encoding": "base64" }]);
+        let response: RpcContextValue<Vec<Option<RpcAccount>>> = self.send("getMultipleAccounts", params).await?;
+        return response.value.into_iter()
+            .map(|account| account.map(RpcAccount::into_account).transpose())
+            .collect();
+    }
+}
+
+/// JSON RPC server on a local port answering with canned responses
+#[cfg(test)]
+pub mod testing {
+    use serde_json::Value;
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    pub struct StubRpc {
+        pub url: String,
+        methods: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl StubRpc {
+        /// Answers every request with the body `answer` returns for its method, or a 500 without one
+        pub fn start<F: Fn(&str) -> Option<String> + Send + 'static>(answer: F) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/", listener.local_addr().unwrap());
+            let methods = Arc::new(Mutex::new(Vec::new()));
+            let received = methods.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = match stream {
+                        Ok(stream) => stream,
+                        Err(_) => return,
+                    };
+                    let request: Value = serde_json::from_slice(&read_body(&mut stream)).unwrap_or(Value::Null);
+                    let method = request["method"].as_str().unwrap_or_default().to_string();
+                    received.lock().unwrap().push(method.clone());
+                    let (status, body) = match answer(&method) {
+                        Some(body) => ("200 OK", body),
+                        None => ("500 Internal Server Error", String::new()),
+                    };
+                    let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+                }
+            });
+            return Self { url, methods };
+        }
+
+        /// Number of requests received for `method`
+        pub fn calls(&self, method: &str) -> usize {
+            return self.methods.lock().unwrap().iter().filter(|received| *received == method).count();
+        }
+    }
+
+    pub fn result(result: Value) -> Option<String> {
+        return Some(serde_json::json!({ "jsonrpc": "2.0", "result": result, "id": 1 }).to_string());
+    }
+
+    fn read_body(stream: &mut TcpStream) -> Vec<u8> {
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 4096];
+        loop {
+            let read = stream.read(&mut buffer).unwrap_or(0);
+            if read == 0 {
+                return Vec::new();
+            }
+            request.extend_from_slice(&buffer[..read]);
+            let headers_end = match request.windows(4).position(|window| window == b"\r\n\r\n") {
+                Some(position) => position + 4,
+                None => continue,
+            };
+            let headers = String::from_utf8_lossy(&request[..headers_end]).to_lowercase();
+            let length = headers.lines()
+                .find_map(|line| line.strip_prefix("content-length:").map(|length| length.trim().parse::<usize>().unwrap_or(0)))
+                .unwrap_or(0);
+            if request.len() >= headers_end + length {
+                return request[headers_end..headers_end + length].to_vec();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{result, StubRpc};
+    use super::*;
+
+    const OWNER: &str = "So1endDq2YkqhipRh3WViPa8hdiSpxWy6z3Z6tMCpAo";
+
+    fn account(data: &str) -> Value {
+        return json!({ "lamports": 5, "data": [data, "base64"], "owner": OWNER, "executable": false, "rentEpoch": 7 });
+    }
+
+    fn client(stub: &StubRpc) -> RpcClient {
+        return RpcClient::new_with_timeout(stub.url.clone(), Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn error_object() {
+        let stub = StubRpc::start(|_| Some(r#"{"jsonrpc":"2.0","error":{"code":-32005,"message":"Node is behind by 42 slots"},"id":1}"#.to_string()));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        match runtime.block_on(client(&stub).get_slot()) {
+            Err(Error::Rpc(message)) => assert_eq!(message, "getSlot failed with code -32005: Node is behind by 42 slots"),
+            other => panic!("Unexpected result {:?}", other.map_err(|e| e.to_string())),
+        }
+
+        // Neither a result nor an error
+        let stub = StubRpc::start(|_| Some(r#"{"jsonrpc":"2.0","id":1}"#.to_string()));
+        assert!(matches!(runtime.block_on(client(&stub).get_slot()), Err(Error::Rpc(_))));
+        let stub = StubRpc::start(|_| None);
+        assert!(runtime.block_on(client(&stub).get_slot()).is_err());
+    }
+
+    #[test]
+    fn base64_account_data() {
+        let stub = StubRpc::start(|method| match method {
+            "getAccountInfo" => result(json!({ "context": { "slot": 100 }, "value": account("AQID") })),
+            "getMultipleAccounts" => result(json!({ "context": { "slot": 100 }, "value": [account("BAU="), null] })),
+            _ => None,
+        });
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let rpc_client = client(&stub);
+
+        let account = runtime.block_on(rpc_client.get_account(&Pubkey::new_unique())).unwrap();
+        assert_eq!(account.data, vec![1, 2, 3]);
+        assert_eq!(account.lamports, 5);
+        assert_eq!(account.rent_epoch, 7);
+        assert_eq!(account.owner.to_string(), OWNER);
+
+        let accounts = runtime.block_on(rpc_client.get_multiple_accounts(&[Pubkey::new_unique(), Pubkey::new_unique()])).unwrap();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0].as_ref().unwrap().data, vec![4, 5]);
+        assert!(accounts[1].is_none());
+    }
+
+    #[test]
+    fn invalid_account() {
+        let stub = StubRpc::start(|method| match method {
+            "getAccountInfo" => result(json!({ "context": { "slot": 100 }, "value": account("not base64!") })),
+            "getMultipleAccounts" => result(json!({ "context": { "slot": 100 }, "value": [{ "lamports": 5, "data": ["", "base64"], "owner": "nope", "executable": false, "rentEpoch": 7 }] })),
+            _ => result(json!({ "context": { "slot": 100 }, "value": null })),
+        });
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let rpc_client = client(&stub);
+        assert!(matches!(runtime.block_on(rpc_client.get_account(&Pubkey::new_unique())), Err(Error::Decode(_))));
+        assert!(matches!(runtime.block_on(rpc_client.get_multiple_accounts(&[Pubkey::new_unique()])), Err(Error::Decode(_))));
+
+        let stub = StubRpc::start(|_| result(json!({ "context": { "slot": 100 }, "value": null })));
+        assert!(matches!(runtime.block_on(client(&stub).get_account(&Pubkey::new_unique())), Err(Error::NotFound(_))));
+    }
+}
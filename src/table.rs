@@ -0,0 +1,375 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use crate::apy::APY;
+use crate::error::{AssetError, Error, Result};
+use crate::snapshot::Snapshot;
+use crate::stats::Stats;
+use crate::utils::AssetSymbol;
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TableColumn {
+    ASSET,
+    SUPPLY,
+    BORROW,
+    SUPPLY_APR,
+    BORROW_APR,
+    SUPPLY_REWARDS,
+    BORROW_REWARDS,
+    PRICE,
+    UTILIZATION,
+}
+
+impl TableColumn {
+    pub fn all() -> Vec<TableColumn> {
+        return vec![
+            TableColumn::ASSET,
+            TableColumn::SUPPLY,
+            TableColumn::BORROW,
+            TableColumn::SUPPLY_APR,
+            TableColumn::BORROW_APR,
+            TableColumn::SUPPLY_REWARDS,
+            TableColumn::BORROW_REWARDS,
+            TableColumn::PRICE,
+            TableColumn::UTILIZATION,
+        ];
+    }
+
+    fn title(&self) -> &'static str {
+        return match self {
+            TableColumn::ASSET => "Asset",
+            TableColumn::SUPPLY => "Supply APY",
+            TableColumn::BORROW => "Borrow APY",
+            TableColumn::SUPPLY_APR => "Supply APR",
+            TableColumn::BORROW_APR => "Borrow APR",
+            TableColumn::SUPPLY_REWARDS => "Supply rewards",
+            TableColumn::BORROW_REWARDS => "Borrow rewards",
+            TableColumn::PRICE => "Price",
+            TableColumn::UTILIZATION => "Utilization",
+        };
+    }
+
+    /// Value the rows are sorted on, the asset column is sorted by symbol instead
+    fn value(&self, row: &TableRow) -> Option<f64> {
+        return match self {
+            TableColumn::ASSET => None,
+            TableColumn::SUPPLY => Some(row.supply),
+            TableColumn::BORROW => Some(row.borrow),
+            TableColumn::SUPPLY_APR => row.supply_apr,
+            TableColumn::BORROW_APR => row.borrow_apr,
+            TableColumn::SUPPLY_REWARDS => Some(row.supply_rewards),
+            TableColumn::BORROW_REWARDS => Some(row.borrow_rewards),
+            TableColumn::PRICE => Some(row.price),
+            TableColumn::UTILIZATION => row.utilization,
+        };
+    }
+
+    fn cell(&self, row: &TableRow) -> String {
+        return match self {
+            TableColumn::ASSET => row.asset.to_string(),
+            TableColumn::PRICE => format_price(row.price),
+            _ => self.value(row).map(format_percent).unwrap_or_else(|| "-".to_string()),
+        };
+    }
+}
+
+impl FromStr for TableColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "ASSET" | "asset" => Ok(TableColumn::ASSET),
+            "SUPPLY" | "supply" => Ok(TableColumn::SUPPLY),
+            "BORROW" | "borrow" => Ok(TableColumn::BORROW),
+            "SUPPLY_APR" | "supply_apr" => Ok(TableColumn::SUPPLY_APR),
+            "BORROW_APR" | "borrow_apr" => Ok(TableColumn::BORROW_APR),
+            "SUPPLY_REWARDS" | "supply_rewards" => Ok(TableColumn::SUPPLY_REWARDS),
+            "BORROW_REWARDS" | "borrow_rewards" => Ok(TableColumn::BORROW_REWARDS),
+            "PRICE" | "price" => Ok(TableColumn::PRICE),
+            "UTILIZATION" | "utilization" => Ok(TableColumn::UTILIZATION),
+            _ => Err(format!("'{}' is not a valid value for TableColumn", s)),
+        }
+    }
+}
+
+impl fmt::Display for TableColumn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TableFormat {
+    TEXT,
+    JSON,
+    CSV,
+    MARKDOWN,
+}
+
+impl FromStr for TableFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "TEXT" | "text" => Ok(TableFormat::TEXT),
+            "JSON" | "json" => Ok(TableFormat::JSON),
+            "CSV" | "csv" => Ok(TableFormat::CSV),
+            "MARKDOWN" | "markdown" => Ok(TableFormat::MARKDOWN),
+            _ => Err(format!("'{}' is not a valid value for TableFormat", s)),
+        }
+    }
+}
+
+impl fmt::Display for TableFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// APYs of an asset as shown in the table. The supply rewards include the MNDE ones.
+#[derive(Serialize, Debug, Clone)]
+pub struct TableRow {
+    pub asset: AssetSymbol,
+    pub name: String,
+    pub supply: f64,
+    pub borrow: f64,
+    pub supply_apr: Option<f64>,
+    pub borrow_apr: Option<f64>,
+    pub supply_rewards: f64,
+    pub borrow_rewards: f64,
+    pub price: f64,
+    pub utilization: Option<f64>,
+}
+
+impl TableRow {
+    pub fn new(apy: &APY) -> Self {
+        return Self {
+            asset: apy.asset.clone(),
+            name: apy.name.clone(),
+            supply: apy.supply,
+            borrow: apy.borrow,
+            supply_apr: apy.supply_apr,
+            borrow_apr: apy.borrow_apr,
+            supply_rewards: apy.supply_rewards + apy.mnde_supply_rewards.unwrap_or_default(),
+            borrow_rewards: apy.borrow_rewards,
+            price: apy.price,
+            utilization: apy.utilization,
+        };
+    }
+}
+
+/// APYs and stats of a market snapshot, printed without the web page
+#[derive(Serialize, Debug, Clone)]
+pub struct APYTable {
+    pub market: String,
+    pub slot: u64,
+    pub last_updated: DateTime<Utc>,
+    pub rows: Vec<TableRow>,
+    pub errors: Vec<AssetError>,
+    pub warnings: Vec<String>,
+    pub stats: Stats,
+}
+
+impl APYTable {
+    pub fn new(snapshot: &Snapshot) -> Self {
+        return Self {
+            market: snapshot.market.clone(),
+            slot: snapshot.slot,
+            last_updated: snapshot.last_updated,
+            rows: snapshot.apys.apys.iter().map(TableRow::new).collect(),
+            errors: snapshot.apys.errors.clone(),
+            warnings: snapshot.warnings.clone(),
+            stats: snapshot.stats.clone(),
+        };
+    }
+
+    /// Sorts the rows highest value first, or by symbol for the asset column, `reverse` flips the order.
+    /// Rows without a value come last either way.
+    pub fn sort(&mut self, column: TableColumn, reverse: bool) {
+        self.rows.sort_by(|a, b| {
+            let ordering = match (column.value(a), column.value(b)) {
+                (Some(a), Some(b)) => b.partial_cmp(&a).unwrap_or(Ordering::Equal),
+                (Some(_), None) => return Ordering::Less,
+                (None, Some(_)) => return Ordering::Greater,
+                (None, None) if column == TableColumn::ASSET => a.asset.0.cmp(&b.asset.0),
+                (None, None) => Ordering::Equal,
+            };
+            if reverse { ordering.reverse() } else { ordering }
+        });
+    }
+
+    pub fn render(&self, format: TableFormat) -> Result<String> {
+        return match format {
+            TableFormat::TEXT => Ok(self.render_text()),
+            TableFormat::JSON => Ok(serde_json::to_string_pretty(self)?),
+            TableFormat::CSV => self.render_csv(),
+            TableFormat::MARKDOWN => Ok(self.render_markdown()),
+        };
+    }
+
+    fn cells(&self) -> Vec<Vec<String>> {
+        return self.rows.iter()
+            .map(|row| TableColumn::all().iter().map(|column| column.cell(row)).collect())
+            .collect();
+    }
+
+    /// Market totals, the assets left out and the rewards or prices missing, below the table
+    fn footer(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "{} market at slot {} ({}): {} supplied, {} borrowed, SLND {}, MNDE {}",
+            self.market, self.slot, self.last_updated.format("%Y-%m-%d %H:%M:%S UTC"),
+            format_total(self.stats.total_supplied), format_total(self.stats.total_borrowed),
+            format_price(self.stats.slnd_price), format_price(self.stats.mnde_price),
+        )];
+        for asset_error in self.errors.iter() {
+            lines.push(format!("{} unavailable: {}", asset_error.asset, asset_error.error));
+        }
+        lines.extend(self.warnings.iter().cloned());
+        return lines;
+    }
+
+    /// Columns padded to their widest cell, the symbols aligned left and the numbers right
+    fn render_text(&self) -> String {
+        let columns = TableColumn::all();
+        let cells = self.cells();
+        let widths: Vec<usize> = columns.iter().enumerate()
+            .map(|(index, column)| cells.iter().map(|row| row[index].len()).fold(column.title().len(), usize::max))
+            .collect();
+        let line = |values: Vec<&str>| -> String {
+            values.iter().enumerate()
+                .map(|(index, value)| if index == 0 { format!("{:<width$}", value, width = widths[index]) } else { format!("{:>width$}", value, width = widths[index]) })
+                .collect::<Vec<String>>()
+                .join("  ")
+        };
+
+        let mut lines = vec![line(columns.iter().map(|column| column.title()).collect())];
+        lines.push(widths.iter().map(|width| "-".repeat(*width)).collect::<Vec<String>>().join("  "));
+        for row in cells.iter() {
+            lines.push(line(row.iter().map(String::as_str).collect()));
+        }
+        lines.push(String::new());
+        lines.extend(self.footer());
+        return lines.join("\n");
+    }
+
+    fn render_markdown(&self) -> String {
+        let columns = TableColumn::all();
+        let mut lines = vec![format!("| {} |", columns.iter().map(|column| column.title()).collect::<Vec<&str>>().join(" | "))];
+        lines.push(format!("| --- |{}", " ---: |".repeat(columns.len() - 1)));
+        for row in self.cells() {
+            lines.push(format!("| {} |", row.join(" | ")));
+        }
+        lines.push(String::new());
+        lines.extend(self.footer());
+        return lines.join("\n");
+    }
+
+    /// Raw values, the APYs and the utilization as fractions
+    fn render_csv(&self) -> Result<String> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        if self.rows.is_empty() {
+            writer.write_record(["asset", "name", "supply", "borrow", "supply_apr", "borrow_apr", "supply_rewards", "borrow_rewards", "price", "utilization"])?;
+        }
+        for row in self.rows.iter() {
+            writer.serialize(row)?;
+        }
+        let body = writer.into_inner().map_err(|e| Error::Decode(e.to_string()))?;
+        return String::from_utf8(body).map_err(|e| Error::Decode(e.to_string()));
+    }
+}
+
+fn format_percent(value: f64) -> String {
+    return format!("{:.2}%", value * 100f64);
+}
+
+/// Cents for most prices, more digits for the tokens worth less than a dollar
+fn format_price(price: f64) -> String {
+    if price < 1f64 {
+        return format!("${:.4}", price);
+    }
+    return format!("${:.2}", price);
+}
+
+/// Rounded to millions, the totals run in the hundreds of them
+fn format_total(total: f64) -> String {
+    return format!("${:.1}M", total / 1_000_000f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn row(asset: &str, supply: f64, utilization: Option<f64>) -> TableRow {
+        return TableRow {
+            asset: AssetSymbol(asset.to_string()),
+            name: asset.to_string(),
+            supply,
+            borrow: supply * 2f64,
+            supply_apr: Some(supply),
+            borrow_apr: Some(supply * 2f64),
+            supply_rewards: 0.01,
+            borrow_rewards: 0.02,
+            price: 1.0,
+            utilization,
+        };
+    }
+
+    fn table(rows: Vec<TableRow>) -> APYTable {
+        return APYTable {
+            market: "main".to_string(),
+            slot: 100,
+            last_updated: Utc.ymd(2021, 11, 3).and_hms(10, 0, 0),
+            rows,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            stats: Stats { market: "main".to_string(), slnd_price: 2.0, mnde_price: 0.4, total_supplied: 1e6, total_borrowed: 5e5 },
+        };
+    }
+
+    fn assets(table: &APYTable) -> Vec<&str> {
+        return table.rows.iter().map(|row| row.asset.0.as_str()).collect();
+    }
+
+    #[test]
+    fn sort_by_value() {
+        let mut table = table(vec![row("USDC", 0.02, Some(0.6)), row("SOL", 0.05, Some(0.4)), row("ETH", 0.01, Some(0.8))]);
+        table.sort(TableColumn::SUPPLY, false);
+        assert_eq!(assets(&table), vec!["SOL", "USDC", "ETH"]);
+        table.sort(TableColumn::SUPPLY, true);
+        assert_eq!(assets(&table), vec!["ETH", "USDC", "SOL"]);
+        table.sort(TableColumn::ASSET, false);
+        assert_eq!(assets(&table), vec!["ETH", "SOL", "USDC"]);
+        table.sort(TableColumn::ASSET, true);
+        assert_eq!(assets(&table), vec!["USDC", "SOL", "ETH"]);
+    }
+
+    #[test]
+    fn rows_without_a_value_come_last() {
+        let mut table = table(vec![row("MSOL", 0.03, None), row("USDC", 0.02, Some(0.6)), row("SRM", 0.04, None), row("SOL", 0.05, Some(0.4))]);
+        table.sort(TableColumn::UTILIZATION, false);
+        assert_eq!(assets(&table)[..2], ["USDC", "SOL"]);
+        assert_eq!(table.rows[2].utilization, None);
+        assert_eq!(table.rows[3].utilization, None);
+
+        // Either way
+        table.sort(TableColumn::UTILIZATION, true);
+        assert_eq!(assets(&table)[..2], ["SOL", "USDC"]);
+        assert_eq!(table.rows[2].utilization, None);
+        assert_eq!(table.rows[3].utilization, None);
+    }
+
+    #[test]
+    fn csv_header_without_rows() {
+        let header = "asset,name,supply,borrow,supply_apr,borrow_apr,supply_rewards,borrow_rewards,price,utilization";
+        assert_eq!(table(Vec::new()).render(TableFormat::CSV).unwrap(), format!("{}\n", header));
+
+        // The same header as the one written from the rows
+        let body = table(vec![row("SOL", 0.05, None)]).render(TableFormat::CSV).unwrap();
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines, vec![header, "SOL,SOL,0.05,0.1,0.05,0.1,0.01,0.02,1.0,"]);
+    }
+}
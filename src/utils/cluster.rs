@@ -0,0 +1,182 @@
+use config::ConfigError;
+use log::{error, info};
+use solana_sdk::pubkey::Pubkey;
+use std::{collections::HashMap, fmt, fs, path::Path, path::PathBuf, str::FromStr};
+
+use crate::utils::{AssetSymbol, ProgramConfig};
+use crate::{DEVNET_CONFIG_JSON, PRODUCTION_CONFIG_JSON};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Cluster {
+    MAINNET,
+    DEVNET,
+    LOCALNET,
+}
+
+impl Cluster {
+    pub fn rpc_url(&self) -> &'static str {
+        return match self {
+            Cluster::MAINNET => "https://solana-api.projectserum.com/",
+            Cluster::DEVNET => "https://api.devnet.solana.com/",
+            Cluster::LOCALNET => "http://127.0.0.1:8899/",
+        };
+    }
+
+    /// Program config compiled into the binary, localnet deployments have to provide their own
+    pub fn embedded_config(&self) -> Option<&'static str> {
+        return match self {
+            Cluster::MAINNET => Some(PRODUCTION_CONFIG_JSON),
+            Cluster::DEVNET => Some(DEVNET_CONFIG_JSON),
+            Cluster::LOCALNET => None,
+        };
+    }
+
+    pub fn default_cache_path(&self) -> PathBuf {
+        return PathBuf::from(format!("cache/program_config.{}.json", self.to_string().to_lowercase()));
+    }
+}
+
+impl FromStr for Cluster {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "MAINNET" | "mainnet" | "mainnet-beta" => Ok(Cluster::MAINNET),
+            "DEVNET" | "devnet" => Ok(Cluster::DEVNET),
+            "LOCALNET" | "localnet" => Ok(Cluster::LOCALNET),
+            _ => Err(format!("'{}' is not a valid value for Cluster", s)),
+        }
+    }
+}
+
+impl fmt::Display for Cluster {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl ProgramConfig {
+    /// Loads the program config from `location` (a URL or a file path) and keeps a copy in
+    /// `cache_path`. When the location is unreachable or invalid, the last cached copy is used,
+    /// and as a last resort the config embedded for the cluster.
+    pub async fn load(cluster: Cluster, location: Option<&str>, cache_path: &Path) -> Result<Self, ConfigError> {
+        if let Some(location) = location {
+            match Self::fetch(location).await {
+                Ok((program_config, body)) => {
+                    info!("Program config loaded from {}", location);
+                    if let Err(e) = fs::create_dir_all(cache_path.parent().unwrap_or(Path::new(""))).and_then(|_| fs::write(cache_path, body)) {
+                        error!("Could not cache program config in {}: {}", cache_path.display(), e);
+                    }
+                    return Ok(program_config);
+                },
+                Err(e) => error!("Could not load program config from {}: {}", location, e),
+            }
+
+            match fs::read_to_string(cache_path).map_err(|e| ConfigError::Foreign(Box::new(e))).and_then(|body| Self::from_json(&body)) {
+                Ok(program_config) => {
+                    info!("Program config loaded from cache {}", cache_path.display());
+                    return Ok(program_config);
+                },
+                Err(e) => error!("Could not load cached program config from {}: {}", cache_path.display(), e),
+            }
+        }
+
+        match cluster.embedded_config() {
+            Some(json) => {
+                info!("Using embedded {} program config", cluster);
+                Self::from_json(json)
+            },
+            None => Err(ConfigError::Message(format!("There is no program config available for {}", cluster))),
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, ConfigError> {
+        let mut program_config: ProgramConfig = serde_json::from_str(json).map_err(|e| ConfigError::Foreign(Box::new(e)))?;
+        program_config.resolve_mints()?;
+        program_config.validate()?;
+        return Ok(program_config);
+    }
+
+    async fn fetch(location: &str) -> Result<(Self, String), ConfigError> {
+        let body = if location.starts_with("http://") || location.starts_with("https://") {
+            let response = reqwest::get(location).await
+                .and_then(|res| res.error_for_status())
+                .map_err(|e| ConfigError::Foreign(Box::new(e)))?;
+            response.text().await.map_err(|e| ConfigError::Foreign(Box::new(e)))?
+        } else {
+            fs::read_to_string(location).map_err(|e| ConfigError::Foreign(Box::new(e)))?
+        };
+        let program_config = Self::from_json(&body)?;
+        return Ok((program_config, body));
+    }
+
+    /// Reserves are looked up by the mint of their liquidity, listed along with the assets
+    fn resolve_mints(&mut self) -> Result<(), ConfigError> {
+        let mints: HashMap<AssetSymbol, String> = self.assets.iter().map(|asset| (asset.symbol.clone(), asset.mint_address.clone())).collect();
+        for market in self.markets.iter_mut() {
+            for reserve in market.reserves.iter_mut().filter(|reserve| reserve.mint_address.is_empty()) {
+                reserve.mint_address = mints.get(&reserve.asset).cloned()
+                    .ok_or(ConfigError::Message(format!("Reserve {} in {} market has no asset with its mint", reserve.asset, market.name)))?;
+            }
+        }
+        return Ok(());
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.markets.is_empty() {
+            return Err(ConfigError::Message("Program config has no markets".to_string()));
+        }
+        for market in self.markets.iter() {
+            for reserve in market.reserves.iter() {
+                if Pubkey::from_str(&reserve.address).is_err() {
+                    return Err(ConfigError::Message(format!("Reserve {} in {} market has an invalid address '{}'", reserve.asset, market.name, reserve.address)));
+                }
+                if Pubkey::from_str(&reserve.mint_address).is_err() {
+                    return Err(ConfigError::Message(format!("Reserve {} in {} market has an invalid mint address '{}'", reserve.asset, market.name, reserve.mint_address)));
+                }
+            }
+        }
+        for asset in self.assets.iter() {
+            if Pubkey::from_str(&asset.mint_address).is_err() {
+                return Err(ConfigError::Message(format!("Asset {} has an invalid mint address '{}'", asset.symbol, asset.mint_address)));
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOL_MINT: &str = "So11111111111111111111111111111111111111112";
+
+    #[test]
+    fn reserves_are_found_by_mint() {
+        let program_config = ProgramConfig::from_json(Cluster::MAINNET.embedded_config().unwrap()).unwrap();
+        let market = program_config.market("main").unwrap();
+        for reserve in market.reserves.iter() {
+            let asset = program_config.asset_by_mint(&reserve.mint_address).unwrap();
+            assert_eq!(asset.symbol, reserve.asset);
+        }
+
+        let sol = market.reserve_by_mint(&Pubkey::from_str(SOL_MINT).unwrap()).unwrap();
+        assert_eq!(sol.asset.0, "SOL");
+        assert_eq!(market.find_reserve(SOL_MINT).unwrap().address, sol.address);
+        assert_eq!(market.find_reserve("sol").unwrap().address, sol.address);
+        assert!(market.find_reserve("NOPE").is_none());
+    }
+
+    #[test]
+    fn reserve_without_a_listed_asset() {
+        let mut json: serde_json::Value = serde_json::from_str(Cluster::MAINNET.embedded_config().unwrap()).unwrap();
+        json["assets"].as_array_mut().unwrap().retain(|asset| asset["symbol"] != "SOL");
+        assert!(ProgramConfig::from_json(&json.to_string()).is_err());
+
+        // Unless the reserve sets its mint itself
+        json["markets"][0]["reserves"][0]["mintAddress"] = serde_json::Value::String(SOL_MINT.to_string());
+        assert_eq!(json["markets"][0]["reserves"][0]["asset"], "SOL");
+        let program_config = ProgramConfig::from_json(&json.to_string()).unwrap();
+        assert_eq!(program_config.markets[0].reserves[0].mint_address, SOL_MINT);
+    }
+}
@@ -1,5 +1,10 @@
 use config::ConfigError;
 use serde::Deserialize;
+use std::{path::PathBuf, time::Duration};
+
+use crate::db::DataType;
+use crate::error::Error;
+use crate::utils::Cluster;
 
 #[derive(Clone, Deserialize)]
 pub struct Serverconfig {
@@ -23,11 +28,115 @@ pub struct TwitterConfig{
     pub access_secret: String,
 }
 
+#[derive(Clone, Deserialize, Default)]
+pub struct SolanaConfig {
+    pub cluster: Option<String>,
+    pub program_config: Option<String>,
+    pub program_config_cache: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+pub struct RpcConfig {
+    /// Comma separated list of endpoints, in order of preference
+    pub urls: Option<String>,
+    pub timeout: Option<u64>,
+}
+
+impl RpcConfig {
+    pub fn endpoints(&self, cluster: Cluster) -> Vec<String> {
+        let urls: Vec<String> = match &self.urls {
+            Some(urls) => urls.split(',').map(|url| url.trim().to_string()).filter(|url| !url.is_empty()).collect(),
+            None => Vec::new(),
+        };
+        if urls.is_empty() {
+            return vec![cluster.rpc_url().to_string()];
+        }
+        return urls;
+    }
+
+    pub fn timeout(&self) -> Duration {
+        return Duration::from_secs(self.timeout.unwrap_or(120));
+    }
+}
+
+#[derive(Clone, Deserialize, Default)]
+pub struct ScheduleConfig {
+    /// Cron expressions of the daemon jobs, with or without the leading seconds field
+    pub minute: Option<String>,
+    pub hour: Option<String>,
+    pub day: Option<String>,
+    pub week: Option<String>,
+    /// File keeping the last run of every job
+    pub state: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Default)]
+pub struct SnapshotConfig {
+    /// Seconds between two refreshes of the market snapshots
+    pub interval: Option<u64>,
+}
+
+impl SnapshotConfig {
+    pub fn interval(&self) -> Duration {
+        return Duration::from_secs(self.interval.unwrap_or(60));
+    }
+}
+
+#[derive(Clone, Deserialize, Default)]
+pub struct StorageConfig {
+    /// mongodb (default) or sqlite
+    pub backend: Option<String>,
+    /// Database file of the sqlite backend
+    pub path: Option<String>,
+}
+
+impl StorageConfig {
+    pub fn path(&self) -> PathBuf {
+        return PathBuf::from(self.path.as_deref().unwrap_or("data/solend-apy-bot.sqlite"));
+    }
+}
+
+#[derive(Clone, Deserialize, Default)]
+pub struct RetentionConfig {
+    /// Days the data points of every tier are kept, 0 keeps them forever
+    pub minute: Option<i64>,
+    pub hour: Option<i64>,
+    pub day: Option<i64>,
+    pub week: Option<i64>,
+}
+
+impl RetentionConfig {
+    /// MINUTE data points are kept 30 days and HOUR ones a year, once rolled up into the coarser
+    /// tiers. DAY and WEEK data points are kept forever.
+    pub fn days(&self, data_type: DataType) -> Option<i64> {
+        let days = match data_type {
+            DataType::MINUTE => self.minute.unwrap_or(30),
+            DataType::HOUR => self.hour.unwrap_or(365),
+            DataType::DAY => self.day.unwrap_or(0),
+            DataType::WEEK => self.week.unwrap_or(0),
+        };
+        return if days > 0 { Some(days) } else { None };
+    }
+}
+
 #[derive(Clone, Deserialize)]
 pub struct Config {
-    pub server: Serverconfig,
-    pub twitter: TwitterConfig,
-    pub mongodb: DatabaseConfig,
+    /// Only the commands serving the web pages need the server and only `post` and `daemon` need Twitter
+    pub server: Option<Serverconfig>,
+    pub twitter: Option<TwitterConfig>,
+    pub mongodb: Option<DatabaseConfig>,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub solana: SolanaConfig,
+    #[serde(default)]
+    pub rpc: RpcConfig,
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+    #[serde(default)]
+    pub snapshot: SnapshotConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
 }
 
 impl Config {
@@ -36,4 +145,33 @@ impl Config {
         cfg.merge(config::Environment::new())?;
         cfg.try_into()
     }
+
+    pub fn server(&self) -> Result<&Serverconfig, Error> {
+        return self.server.as_ref().ok_or(Error::Config("SERVER.HOST and SERVER.PORT are required to serve the web pages".to_string()));
+    }
+
+    pub fn twitter(&self) -> Result<&TwitterConfig, Error> {
+        return self.twitter.as_ref().ok_or(Error::Config("TWITTER.* is required to post on Twitter".to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_config() {
+        let mut cfg = config::Config::new();
+        cfg.set("storage.backend", "sqlite").unwrap();
+        let config: Config = cfg.try_into().unwrap();
+        assert!(config.server.is_none() && config.twitter.is_none() && config.mongodb.is_none());
+        assert!(matches!(config.server(), Err(Error::Config(_))));
+        assert!(matches!(config.twitter(), Err(Error::Config(_))));
+
+        let mut cfg = config::Config::new();
+        cfg.set("server.host", "127.0.0.1").unwrap();
+        cfg.set("server.port", 8080).unwrap();
+        let config: Config = cfg.try_into().unwrap();
+        assert_eq!(config.server().unwrap().port, 8080);
+    }
 }
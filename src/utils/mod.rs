@@ -1,7 +1,9 @@
 pub mod models;
 pub mod config;
+pub mod cluster;
 pub mod reward_stats;
 
 pub use self::models::*;
 pub use self::config::*;
+pub use self::cluster::*;
 pub use self::reward_stats::*;
\ No newline at end of file
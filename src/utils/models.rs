@@ -1,8 +1,16 @@
 use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
 use std::fmt;
 use std::str::FromStr;
 
-use crate::PRODUCTION_CONFIG_JSON;
+use crate::error::{Error, Result};
+
+/// Name of the main pool, used whenever a market is not specified
+pub const DEFAULT_MARKET: &str = "main";
+
+pub fn default_market() -> String {
+    return DEFAULT_MARKET.to_string();
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all(serialize = "snake_case", deserialize = "camelCase"))]
@@ -12,6 +20,17 @@ pub struct ProgramConfig {
     pub oracles: Oracle,
 }
 
+impl ProgramConfig {
+    /// Finds a lending market by its name or address
+    pub fn market(&self, market: &str) -> Option<&Market> {
+        return self.markets.iter().find(|m| m.name.eq_ignore_ascii_case(market) || m.address == market);
+    }
+
+    pub fn asset_by_mint(&self, mint_address: &str) -> Option<&Asset> {
+        return self.assets.iter().find(|a| a.mint_address == mint_address);
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all(serialize = "snake_case", deserialize = "camelCase"))]
 pub struct Asset {
@@ -21,7 +40,7 @@ pub struct Asset {
     pub mint_address: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all(serialize = "snake_case", deserialize = "camelCase"))]
 pub struct Market {
     pub name: String,
@@ -31,7 +50,42 @@ pub struct Market {
     pub reserves: Vec<Reserve>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Market {
+    pub fn assets(&self) -> Vec<AssetSymbol> {
+        return self.reserves.iter().map(|r| r.asset.clone()).collect();
+    }
+
+    pub fn reserve(&self, asset_symbol: &AssetSymbol) -> Result<&Reserve> {
+        return self.reserves.iter().find(|r| r.asset == *asset_symbol)
+            .ok_or(Error::NotFound(format!("{} reserve in {} market", asset_symbol, self.name)));
+    }
+
+    /// Reserve lending the liquidity of `mint`, which stays the same when an asset is renamed
+    pub fn reserve_by_mint(&self, mint: &Pubkey) -> Result<&Reserve> {
+        let mint_address = mint.to_string();
+        return self.reserves.iter().find(|r| r.mint_address == mint_address)
+            .ok_or(Error::NotFound(format!("Reserve of mint {} in {} market", mint_address, self.name)));
+    }
+
+    /// Reserve of an asset given by its mint address or its symbol, in any case
+    pub fn find_reserve(&self, asset: &str) -> Option<&Reserve> {
+        return self.reserves.iter().find(|r| r.mint_address == asset)
+            .or_else(|| self.reserves.iter().find(|r| r.asset.0.eq_ignore_ascii_case(asset)));
+    }
+
+    pub fn reserve_pubkeys(&self, assets: &[AssetSymbol]) -> Result<Vec<Pubkey>> {
+        let mut account_pks = Vec::<Pubkey>::new();
+        for asset_symbol in assets {
+            let reserve_json = self.reserve(asset_symbol)?;
+            let reserve_pk = Pubkey::from_str(&reserve_json.address)
+                .map_err(|e| Error::Config(format!("Invalid {} reserve address: {}", asset_symbol, e)))?;
+            account_pks.push(reserve_pk);
+        }
+        return Ok(account_pks);
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all(serialize = "snake_case", deserialize = "camelCase"))]
 pub struct Reserve {
     pub asset: AssetSymbol,
@@ -41,6 +95,16 @@ pub struct Reserve {
     pub liquidity_address: String,
     pub liquidity_fee_receiver_address: String,
     pub user_supply_cap: Option<f64>,
+    /// Mint of the liquidity, taken from the asset of the same symbol unless the config sets it
+    #[serde(default)]
+    pub mint_address: String,
+}
+
+impl Reserve {
+    pub fn mint(&self) -> Result<Pubkey> {
+        return Pubkey::from_str(&self.mint_address)
+            .map_err(|e| Error::Config(format!("Invalid {} mint address: {}", self.asset, e)));
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -62,78 +126,25 @@ pub struct OracleAsset {
     pub switchboard_feed_address: String,
 }
 
-#[allow(non_camel_case_types)]
-#[derive(Serialize, Deserialize, Clone, Copy, Debug, Hash, Eq, PartialEq)]
-pub enum AssetSymbol {
-    // TODO: Auto generate this form PRODUCTION_CONFIG_JSON
-    SOL,
-    USDC,
-    USDT,
-    ETH,
-    BTC,
-    SRM,
-    FTT,
-    RAY,
-    MER,
-    SBR,
-    MNGO,
-    mSOL,
-    PAI,
-    UST,
-    weWETH,
-    SLND,
-    scnSOL,
-    stSOL,
-}
-
-#[allow(dead_code)]
-impl AssetSymbol {
-    pub fn name(&self) -> String {
-        let assets = serde_json::from_str::<ProgramConfig>(PRODUCTION_CONFIG_JSON).unwrap().assets;
-        return match self {
-            _ => assets.iter().find(|a| a.symbol == *self).unwrap().name.clone(),
-        };
-    }
-}
-
-impl FromStr for AssetSymbol {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let s = s.to_uppercase();
-        let s = s.as_str();
-        match s {
-            "SOL" => Ok(AssetSymbol::SOL),
-            "USDC" => Ok(AssetSymbol::USDC),
-            "USDT" => Ok(AssetSymbol::USDT),
-            "ETH" => Ok(AssetSymbol::ETH),
-            "BTC" => Ok(AssetSymbol::BTC),
-            "SRM" => Ok(AssetSymbol::SRM),
-            "FTT" => Ok(AssetSymbol::FTT),
-            "RAY" => Ok(AssetSymbol::RAY),
-            "MER" => Ok(AssetSymbol::MER),
-            "SBR" => Ok(AssetSymbol::SBR),
-            "MNGO" => Ok(AssetSymbol::MNGO),
-            "MSOL" => Ok(AssetSymbol::mSOL),
-            "PAI" => Ok(AssetSymbol::PAI),
-            "UST" => Ok(AssetSymbol::UST),
-            "weETH" => Ok(AssetSymbol::weWETH),
-            "SLND" => Ok(AssetSymbol::SLND),
-            "STSOL" => Ok(AssetSymbol::stSOL),
-            "SCNSOL" => Ok(AssetSymbol::scnSOL),
-            _ => Err(format!("'{}' is not a valid value for AssetSymbol", s)),
-        }
-    }
-}
+/// Symbol of an asset as listed in the program config, e.g. `SOL` or `mSOL`
+#[derive(Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct AssetSymbol(pub String);
 
 impl fmt::Display for AssetSymbol {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
+        write!(f, "{}", self.0)
     }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ChartData {
     pub name: AssetSymbol,
+    /// Daily means
     pub data: Vec<(String, f64)>,
+    /// Daily minimums and maximums, drawn as a band around the means
+    #[serde(default)]
+    pub low: Vec<(String, f64)>,
+    #[serde(default)]
+    pub high: Vec<(String, f64)>,
 }
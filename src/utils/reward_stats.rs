@@ -8,6 +8,8 @@ pub struct TokenRewardStat {
     pub borrow: Option<Reward>,
 }
 
+/// Every field is required to accept a reward stat, only the rates are used
+#[allow(dead_code)]
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all(serialize = "snake_case", deserialize = "camelCase"))]
 pub struct Reward {
@@ -19,6 +21,7 @@ pub struct Reward {
     pub reward_rates: Option<Vec<RewardRate>>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all(serialize = "snake_case", deserialize = "camelCase"))]
 pub struct RewardRate {
@@ -41,18 +44,18 @@ where
     }
 
     match StringOrNumber::deserialize(deserializer)? {
-        StringOrNumber::String(s) => Ok(string_to_decimal(s)),
+        StringOrNumber::String(s) => string_to_decimal(s).map_err(serde::de::Error::custom),
         StringOrNumber::Number(i) => Ok(Decimal::from(i as u64)),
         StringOrNumber::Float(f) => Ok(Decimal::from(f as u64)),
     }
 }
 
-fn string_to_decimal(number: String) -> Decimal {
+fn string_to_decimal(number: String) -> Result<Decimal, String> {
     let mut result= Decimal::zero();
     for c in number.chars() {
-        let digit = c.to_string().parse::<u64>().unwrap();
-        let decimal= Decimal::from(digit).try_div(Decimal::from(10_u64.pow(18))).unwrap();
-        result = result.try_mul(10).unwrap().try_add(decimal).unwrap();
+        let digit = c.to_digit(10).ok_or(format!("'{}' is not a valid reward rate", number))? as u64;
+        let decimal= Decimal::from(digit).try_div(Decimal::from(10_u64.pow(18))).map_err(|e| e.to_string())?;
+        result = result.try_mul(10).and_then(|r| r.try_add(decimal)).map_err(|e| e.to_string())?;
     }
-    result.try_div(10_u64.pow(18)).unwrap()
+    result.try_div(10_u64.pow(18)).map_err(|e| e.to_string())
 }
\ No newline at end of file